@@ -0,0 +1,192 @@
+// diagnostics.rs
+
+use serde::Serialize;
+use std::{collections::HashMap, fmt};
+
+/// JSON pointer -> 1-based (line, column) in the source file.
+pub type Positions = HashMap<String, (usize, usize)>;
+
+/// One validation problem, located by JSON pointer and (when known) line/column in the source.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub pointer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// A diagnostic whose position is looked up in `positions` (see `locate_json`).
+    pub fn at(
+        positions: &Positions,
+        pointer: String,
+        message: String,
+    ) -> Self {
+        let pos = positions.get(&pointer).copied();
+        Diagnostic {
+            pointer,
+            line: pos.map(|p| p.0),
+            column: pos.map(|p| p.1),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(line), Some(col)) = (self.line, self.column) {
+            write!(f, "{}:{} ", line, col)?;
+        }
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{}: {}", pointer, self.message)
+    }
+}
+
+/// Build a JSON pointer from path segments, escaping `~` and `/` (RFC 6901).
+pub fn pointer(segments: &[&str]) -> String {
+    segments
+        .iter()
+        .map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Map every JSON pointer in `src` to the 1-based line/column where it starts.
+/// Object members point at their key; array elements at the value.
+/// Meant for text that already parsed successfully; on malformed input it stops early.
+pub fn locate_json(src: &str) -> Positions {
+    let mut scanner = Scanner {
+        chars: src.chars().collect(),
+        pos: 0,
+        line: 1,
+        col: 1,
+        out: HashMap::new(),
+    };
+    scanner.skip_ws();
+    let start = (scanner.line, scanner.col);
+    scanner.out.insert(String::new(), start);
+    scanner.value(String::new());
+    scanner.out
+}
+
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    out: Positions,
+}
+
+impl Scanner {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.bump()? != '"' {
+            return None;
+        }
+        let mut s = String::new();
+        loop {
+            match self.bump()? {
+                '"' => return Some(s),
+                '\\' => {
+                    let esc = self.bump()?;
+                    match esc {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        'r' => s.push('\r'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let hex: String = (0..4).filter_map(|_| self.bump()).collect();
+                            let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                            s.push(c.unwrap_or('\u{fffd}'));
+                        }
+                        other => s.push(other),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self, path: String) -> Option<()> {
+        self.skip_ws();
+        match self.peek()? {
+            '{' => {
+                self.bump();
+                loop {
+                    self.skip_ws();
+                    if self.peek()? == '}' {
+                        self.bump();
+                        return Some(());
+                    }
+                    let at = (self.line, self.col);
+                    let key = self.string()?;
+                    let child = format!("{}{}", path, pointer(&[&key]));
+                    self.out.insert(child.clone(), at);
+                    self.skip_ws();
+                    if self.bump()? != ':' {
+                        return None;
+                    }
+                    self.value(child)?;
+                    self.skip_ws();
+                    if self.peek()? == ',' {
+                        self.bump();
+                    }
+                }
+            }
+            '[' => {
+                self.bump();
+                let mut index = 0;
+                loop {
+                    self.skip_ws();
+                    if self.peek()? == ']' {
+                        self.bump();
+                        return Some(());
+                    }
+                    let child = format!("{}/{}", path, index);
+                    self.out.insert(child.clone(), (self.line, self.col));
+                    self.value(child)?;
+                    index += 1;
+                    self.skip_ws();
+                    if self.peek()? == ',' {
+                        self.bump();
+                    }
+                }
+            }
+            '"' => self.string().map(|_| ()),
+            _ => {
+                // number, true, false, null
+                while matches!(self.peek(), Some(c) if !c.is_whitespace() && !",]}".contains(c)) {
+                    self.bump();
+                }
+                Some(())
+            }
+        }
+    }
+}
@@ -0,0 +1,489 @@
+// formats.rs
+
+use clap::ValueEnum;
+use serde_json::{Map, Number, Value};
+use std::{collections::HashMap, fs, path::Path};
+use toml_edit::{ImDocument, Item};
+use yaml_rust2::{
+    parser::Parser as YamlParser,
+    scanner::{Marker, TScalarStyle},
+    yaml::Hash,
+    Event, Yaml, YamlEmitter,
+};
+
+use crate::args::diagnostics::{locate_json, pointer, Diagnostic, Positions};
+use crate::args::json_to_array::Config;
+use crate::args::{migrate, nuon};
+
+/// A user config format, picked by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+    Nuon,
+}
+
+/// Extensions `Format::from_path` recognises, in the order they're listed in messages.
+pub const EXTENSIONS: [&str; 5] = ["json", "toml", "yaml", "yml", "nuon"];
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "nuon" => Some(Format::Nuon),
+            _ => None,
+        }
+    }
+}
+
+/// Read a config file in whatever format its extension says, into the same `Config`.
+/// Also returns the raw value (in document order) and where each pointer sits in the file.
+pub fn read_config(path: &Path) -> Result<(Config, Value, Positions), Diagnostic> {
+    let format = Format::from_path(path).ok_or_else(|| {
+        whole_file(format!(
+            "Unsupported config extension; expected one of .{}",
+            EXTENSIONS.join(", .")
+        ))
+    })?;
+    let src = fs::read_to_string(path)
+        .map_err(|e| whole_file(format!("Failed to load {}: {}", path.display(), e)))?;
+    let (mut value, positions) = parse(format, &src)?;
+
+    let from = migrate::upgrade(&mut value)
+        .map_err(|e| Diagnostic::at(&positions, pointer(&["version"]), e))?;
+    if from < migrate::CONFIG_VERSION {
+        eprintln!(
+            "(warn) {} is config version {}, upgraded in memory to {}; run `horns migrate {}` to update it",
+            path.display(),
+            from,
+            migrate::CONFIG_VERSION,
+            path.display()
+        );
+    }
+
+    let config = to_config(&value, &positions)?;
+    Ok((config, value, positions))
+}
+
+/// Deserialize a parsed (and migrated) document into `Config`, locating any type error.
+pub fn to_config(value: &Value, positions: &Positions) -> Result<Config, Diagnostic> {
+    // serde_path_to_error keeps the pointer of a type error so it can be located like any other
+    serde_path_to_error::deserialize(value.clone()).map_err(|e| {
+        let segments: Vec<String> = e
+            .path()
+            .iter()
+            .filter_map(|seg| match seg {
+                serde_path_to_error::Segment::Seq { index } => Some(index.to_string()),
+                serde_path_to_error::Segment::Map { key } => Some(key.clone()),
+                _ => None,
+            })
+            .collect();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        Diagnostic::at(
+            positions,
+            pointer(&segments),
+            format!("Config schema error: {}", e.inner()),
+        )
+    })
+}
+
+fn whole_file(message: String) -> Diagnostic {
+    Diagnostic { pointer: String::new(), line: None, column: None, message }
+}
+
+fn located(line: usize, column: usize, message: String) -> Diagnostic {
+    Diagnostic { pointer: String::new(), line: Some(line), column: Some(column), message }
+}
+
+/// Parse `src` into a JSON value plus the line/column of every pointer in it.
+pub fn parse(format: Format, src: &str) -> Result<(Value, Positions), Diagnostic> {
+    match format {
+        Format::Json => {
+            let value: Value = serde_json::from_str(src).map_err(|e| {
+                located(e.line(), e.column(), format!("JSON parse error: {}", e))
+            })?;
+            Ok((value, locate_json(src)))
+        }
+        Format::Toml => parse_toml(src),
+        Format::Yaml => parse_yaml(src),
+        Format::Nuon => nuon::parse(src),
+    }
+}
+
+/// Write `value` out in `format`.
+pub fn render(format: Format, value: &Value) -> Result<String, String> {
+    match format {
+        Format::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+        Format::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+        Format::Yaml => {
+            let mut out = String::new();
+            YamlEmitter::new(&mut out).dump(&to_yaml(value)).map_err(|e| e.to_string())?;
+            Ok(out)
+        }
+        Format::Nuon => {
+            let mut out = String::new();
+            write_nuon(value, 0, &mut out);
+            Ok(out)
+        }
+    }
+}
+
+/// 1-based line and column (in chars) of byte offset `at`.
+fn line_col(src: &str, at: usize) -> (usize, usize) {
+    let before = &src[..at.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, col)
+}
+
+fn parse_toml(src: &str) -> Result<(Value, Positions), Diagnostic> {
+    let doc = ImDocument::parse(src).map_err(|e| {
+        let (line, col) = line_col(src, e.span().map(|s| s.start).unwrap_or(0));
+        located(line, col, format!("TOML parse error: {}", e.message()))
+    })?;
+    let mut out = Positions::new();
+    out.insert(String::new(), (1, 1));
+    let value = toml_table(src, doc.as_table(), String::new(), &mut out);
+    Ok((value, out))
+}
+
+fn toml_table(src: &str, table: &toml_edit::Table, path: String, out: &mut Positions) -> Value {
+    let mut map = Map::new();
+    for (key, item) in table.iter() {
+        let child = format!("{}{}", path, pointer(&[key]));
+        // Keys of implicit tables (`[a.b]` creates `a`) have no span of their own
+        let span = table.key(key).and_then(|k| k.span()).or_else(|| item.span());
+        if let Some(span) = span {
+            out.insert(child.clone(), line_col(src, span.start));
+        }
+        map.insert(key.to_string(), toml_item(src, item, child, out));
+    }
+    Value::Object(map)
+}
+
+fn toml_item(src: &str, item: &Item, path: String, out: &mut Positions) -> Value {
+    match item {
+        Item::None => Value::Null,
+        Item::Value(v) => toml_value(src, v, path, out),
+        Item::Table(t) => toml_table(src, t, path, out),
+        Item::ArrayOfTables(tables) => {
+            let mut items = Vec::new();
+            for (i, t) in tables.iter().enumerate() {
+                let child = format!("{}/{}", path, i);
+                if let Some(span) = t.span() {
+                    out.insert(child.clone(), line_col(src, span.start));
+                }
+                items.push(toml_table(src, t, child, out));
+            }
+            Value::Array(items)
+        }
+    }
+}
+
+fn toml_value(src: &str, value: &toml_edit::Value, path: String, out: &mut Positions) -> Value {
+    use toml_edit::Value as T;
+    match value {
+        T::String(s) => Value::String(s.value().clone()),
+        T::Integer(i) => Value::Number((*i.value()).into()),
+        T::Float(f) => Number::from_f64(*f.value()).map(Value::Number).unwrap_or(Value::Null),
+        T::Boolean(b) => Value::Bool(*b.value()),
+        T::Datetime(d) => Value::String(d.value().to_string()),
+        T::Array(arr) => {
+            let mut items = Vec::new();
+            for (i, v) in arr.iter().enumerate() {
+                let child = format!("{}/{}", path, i);
+                if let Some(span) = v.span() {
+                    out.insert(child.clone(), line_col(src, span.start));
+                }
+                items.push(toml_value(src, v, child, out));
+            }
+            Value::Array(items)
+        }
+        T::InlineTable(table) => {
+            let mut map = Map::new();
+            for (key, v) in table.iter() {
+                let child = format!("{}{}", path, pointer(&[key]));
+                let span = table.key(key).and_then(|k| k.span()).or_else(|| v.span());
+                if let Some(span) = span {
+                    out.insert(child.clone(), line_col(src, span.start));
+                }
+                map.insert(key.to_string(), toml_value(src, v, child, out));
+            }
+            Value::Object(map)
+        }
+    }
+}
+
+/// Walks yaml-rust2 events so every node keeps its marker. Anchors and aliases are resolved;
+/// only the first document is read.
+struct YamlReader<'a> {
+    parser: YamlParser<std::str::Chars<'a>>,
+    out: Positions,
+    anchors: HashMap<usize, Value>,
+}
+
+fn parse_yaml(src: &str) -> Result<(Value, Positions), Diagnostic> {
+    let mut reader = YamlReader {
+        parser: YamlParser::new_from_str(src),
+        out: Positions::new(),
+        anchors: HashMap::new(),
+    };
+    loop {
+        let (event, mark) = reader.next()?;
+        match event {
+            Event::StreamStart | Event::DocumentStart => continue,
+            Event::StreamEnd => return Ok((Value::Null, reader.out)),
+            event => {
+                reader.out.insert(String::new(), (mark.line(), mark.col() + 1));
+                let value = reader.node(event, String::new())?;
+                return Ok((value, reader.out));
+            }
+        }
+    }
+}
+
+impl YamlReader<'_> {
+    fn next(&mut self) -> Result<(Event, Marker), Diagnostic> {
+        self.parser.next_token().map_err(|e| {
+            let mark = e.marker();
+            located(mark.line(), mark.col() + 1, format!("YAML parse error: {}", e.info()))
+        })
+    }
+
+    fn node(&mut self, event: Event, path: String) -> Result<Value, Diagnostic> {
+        let (value, anchor) = match event {
+            Event::Scalar(s, style, anchor, tag) => {
+                let value = if style == TScalarStyle::Plain && tag.is_none() {
+                    match Yaml::from_str(&s) {
+                        Yaml::Integer(i) => Value::Number(i.into()),
+                        Yaml::Real(r) => r
+                            .parse::<f64>()
+                            .ok()
+                            .and_then(Number::from_f64)
+                            .map(Value::Number)
+                            .unwrap_or(Value::String(s)),
+                        Yaml::Boolean(b) => Value::Bool(b),
+                        Yaml::Null => Value::Null,
+                        _ => Value::String(s),
+                    }
+                } else {
+                    Value::String(s)
+                };
+                (value, anchor)
+            }
+            Event::SequenceStart(anchor, _) => {
+                let mut items = Vec::new();
+                loop {
+                    let (event, mark) = self.next()?;
+                    if event == Event::SequenceEnd {
+                        break;
+                    }
+                    let child = format!("{}/{}", path, items.len());
+                    self.out.insert(child.clone(), (mark.line(), mark.col() + 1));
+                    items.push(self.node(event, child)?);
+                }
+                (Value::Array(items), anchor)
+            }
+            Event::MappingStart(anchor, _) => {
+                let mut map = Map::new();
+                loop {
+                    let (event, mark) = self.next()?;
+                    let key = match event {
+                        Event::MappingEnd => break,
+                        Event::Scalar(key, ..) => key,
+                        _ => {
+                            return Err(located(
+                                mark.line(),
+                                mark.col() + 1,
+                                "YAML parse error: only plain string keys are supported".to_string(),
+                            ));
+                        }
+                    };
+                    let child = format!("{}{}", path, pointer(&[&key]));
+                    self.out.insert(child.clone(), (mark.line(), mark.col() + 1));
+                    let (event, _) = self.next()?;
+                    let value = self.node(event, child)?;
+                    map.insert(key, value);
+                }
+                (Value::Object(map), anchor)
+            }
+            Event::Alias(id) => {
+                let value = self.anchors.get(&id).cloned().ok_or_else(|| {
+                    whole_file(format!("YAML parse error: unknown alias at {}", path))
+                })?;
+                (value, 0)
+            }
+            other => {
+                return Err(whole_file(format!("YAML parse error: unexpected {:?} at {}", other, path)));
+            }
+        };
+        if anchor > 0 {
+            self.anchors.insert(anchor, value.clone());
+        }
+        Ok(value)
+    }
+}
+
+fn to_yaml(value: &Value) -> Yaml {
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(n.to_string()),
+        },
+        Value::String(s) => Yaml::String(s.clone()),
+        Value::Array(items) => Yaml::Array(items.iter().map(to_yaml).collect()),
+        Value::Object(map) => {
+            let mut hash = Hash::new();
+            for (k, v) in map {
+                hash.insert(Yaml::String(k.clone()), to_yaml(v));
+            }
+            Yaml::Hash(hash)
+        }
+    }
+}
+
+fn write_nuon(value: &Value, indent: usize, out: &mut String) {
+    let pad = " ".repeat(indent + 4);
+    match value {
+        Value::Object(map) if map.is_empty() => out.push_str("{}"),
+        Value::Array(items) if items.is_empty() => out.push_str("[]"),
+        Value::Object(map) => {
+            out.push_str("{\n");
+            for (k, v) in map {
+                out.push_str(&pad);
+                let bare = !k.is_empty()
+                    && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                if bare {
+                    out.push_str(k);
+                } else {
+                    out.push_str(&nuon_string(k));
+                }
+                out.push_str(": ");
+                write_nuon(v, indent + 4, out);
+                out.push('\n');
+            }
+            out.push_str(&" ".repeat(indent));
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push_str("[\n");
+            for v in items {
+                out.push_str(&pad);
+                write_nuon(v, indent + 4, out);
+                out.push('\n');
+            }
+            out.push_str(&" ".repeat(indent));
+            out.push(']');
+        }
+        Value::String(s) => out.push_str(&nuon_string(s)),
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+/// Double-quoted NUON string; control characters use Nushell's `\u{..}` escape.
+fn nuon_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A config touching every kind of value, plus strings that look like other types or need quoting.
+    fn sample() -> Value {
+        json!({
+            "version": 1,
+            "package_management": {
+                "pre": ["echo 'hi' \"there\"", "a: b # c"],
+                "post": [],
+                "apt": true,
+                "pacstall": { "enabled": true, "noconfirm": true },
+            },
+            "custom_packages": {
+                "pre": [],
+                "post": [],
+                "apt": ["curl", "true", "42", "1.0", "", "yes", "ünï"],
+            },
+            "users": [{ "name": "me", "groups": ["a", "b"], "root": false }],
+            "software": { "nala": false },
+        })
+    }
+
+    #[test]
+    fn every_format_round_trips_to_the_same_json() {
+        let value = sample();
+        for format in [Format::Json, Format::Toml, Format::Yaml, Format::Nuon] {
+            let text = render(format, &value).unwrap();
+            let (back, _) = parse(format, &text).unwrap_or_else(|d| panic!("{:?}: {}\n{}", format, d, text));
+            assert_eq!(back, value, "{:?} changed the config:\n{}", format, text);
+            // And what came back is still a valid Config
+            assert!(to_config(&back, &Positions::new()).is_ok(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_offending_spot() {
+        let cases = [
+            (Format::Json, "{\n  \"version\": 1,\n  \"users\": [,]\n}\n", (3, 13)),
+            (Format::Toml, "version = 1\nusers = [\n  nope\n]\n", (3, 3)),
+            (Format::Yaml, "version: 1\nusers: a: b\n", (2, 9)),
+            (Format::Nuon, "{\n  version: 1\n  users: [ { name: } ]\n}\n", (3, 20)),
+        ];
+        for (format, src, (line, column)) in cases {
+            let d = parse(format, src).err().unwrap();
+            assert_eq!((d.line, d.column), (Some(line), Some(column)), "{:?}: {}", format, d);
+        }
+    }
+
+    #[test]
+    fn schema_errors_point_at_the_key() {
+        // users[0].name is a number in each; the location is where `name` is written
+        let cases = [
+            (
+                Format::Json,
+                "{\n  \"version\": 1,\n  \"package_management\": { \"pre\": [], \"post\": [] },\n  \"custom_packages\": { \"pre\": [], \"post\": [] },\n  \"users\": [ { \"name\": 5 } ]\n}\n",
+                (5, 16),
+            ),
+            (
+                Format::Toml,
+                "version = 1\n\n[package_management]\npre = []\npost = []\n\n[custom_packages]\npre = []\npost = []\n\n[[users]]\nname = 5\n",
+                (12, 1),
+            ),
+            (
+                Format::Yaml,
+                "version: 1\npackage_management:\n  pre: []\n  post: []\ncustom_packages:\n  pre: []\n  post: []\nusers:\n  - name: 5\n",
+                (9, 5),
+            ),
+            (
+                Format::Nuon,
+                "{\n  version: 1\n  package_management: { pre: [], post: [] }\n  custom_packages: { pre: [], post: [] }\n  users: [ { name: 5 } ]\n}\n",
+                (5, 14),
+            ),
+        ];
+        for (format, src, (line, column)) in cases {
+            let (value, positions) = parse(format, src).unwrap();
+            let d = to_config(&value, &positions).err().unwrap();
+            assert_eq!(d.pointer, "/users/0/name", "{:?}", format);
+            assert_eq!((d.line, d.column), (Some(line), Some(column)), "{:?}: {}", format, d);
+        }
+    }
+}
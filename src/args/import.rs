@@ -0,0 +1,198 @@
+// import.rs
+
+use serde_json::{Map, Value};
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::args::diagnostics::{pointer, Diagnostic};
+use crate::args::json_to_array::{Config, CustomPackages, ManagerSpec, PackageManagement, UserSpec};
+use crate::args::managers::ManagerDefs;
+use crate::args::{migrate, nuon};
+
+/// Fields of a deploy.nuon user that `UserSpec` understands.
+const USER_FIELDS: [&str; 6] = ["name", "home", "root", "password", "groups", "comment"];
+
+/// Convert a rhino-deploy `deploy.nuon` into a horns `Config`.
+///
+/// Layout mapping:
+///   packages.managers.<id>  -> package_management.<id>
+///   packages.software.<id>  -> software.<id>
+///   packages.packages.<id>  -> custom_packages.<id> (and enables <id>, as rhino-deploy did)
+///   users                   -> users
+///
+/// Everything else is left out and returned as a located note, one per key.
+pub fn from_nuon(path: &Path, defs: &ManagerDefs) -> Result<(Config, Vec<Diagnostic>), io::Error> {
+    let src = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Cannot read {}: {}", path.display(), e)))?;
+    let (doc, positions) = nuon::parse(&src)
+        .map_err(|d| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), d)))?;
+
+    let mut notes: Vec<Diagnostic> = Vec::new();
+    let mut note = |segments: &[&str], message: String| {
+        notes.push(Diagnostic::at(&positions, pointer(segments), message));
+    };
+
+    let Value::Object(root) = doc else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: expected a record at the top level", path.display()),
+        ));
+    };
+
+    let mut managers: HashMap<String, ManagerSpec> = HashMap::new();
+    let mut by_manager: HashMap<String, Vec<String>> = HashMap::new();
+    let mut software: HashMap<String, bool> = HashMap::new();
+    let mut users: Vec<UserSpec> = Vec::new();
+
+    for (key, value) in root {
+        match (key.as_str(), value) {
+            ("packages", Value::Object(packages)) => {
+                for (section, value) in packages {
+                    let Value::Object(entries) = value else {
+                        note(&["packages", &section], "expected a record".to_string());
+                        continue;
+                    };
+                    match section.as_str() {
+                        "managers" => {
+                            for (id, on) in entries {
+                                let at = ["packages", "managers", id.as_str()];
+                                match on {
+                                    _ if !defs.defs.contains_key(&id) => {
+                                        note(&at, format!("no manager '{}' in Packages.toml", id))
+                                    }
+                                    Value::Bool(on) => {
+                                        let spec = enabled_as_deploy(defs, &id, on);
+                                        managers.insert(id, spec);
+                                    }
+                                    other => note(&at, format!("expected true/false, found {}", other)),
+                                }
+                            }
+                        }
+                        "software" => {
+                            for (id, on) in entries {
+                                let at = ["packages", "software", id.as_str()];
+                                match on {
+                                    _ if !defs.software.contains_key(&id) => {
+                                        note(&at, format!("no software preset '{}' in Packages.toml", id))
+                                    }
+                                    Value::Bool(on) => {
+                                        software.insert(id, on);
+                                    }
+                                    other => note(&at, format!("expected true/false, found {}", other)),
+                                }
+                            }
+                        }
+                        "packages" => {
+                            for (id, list) in entries {
+                                if !defs.defs.contains_key(&id) {
+                                    note(
+                                        &["packages", "packages", &id],
+                                        format!("no manager '{}' in Packages.toml", id),
+                                    );
+                                    continue;
+                                }
+                                let Value::Array(items) = list else {
+                                    note(&["packages", "packages", &id], "expected a list".to_string());
+                                    continue;
+                                };
+                                let mut pkgs = Vec::new();
+                                for (i, item) in items.into_iter().enumerate() {
+                                    match item {
+                                        Value::String(s) => pkgs.push(s),
+                                        other => note(
+                                            &["packages", "packages", &id, &i.to_string()],
+                                            format!("expected a package name, found {}", other),
+                                        ),
+                                    }
+                                }
+                                by_manager.insert(id, pkgs);
+                            }
+                        }
+                        _ => note(&["packages", &section], "no equivalent in horns".to_string()),
+                    }
+                }
+            }
+            ("packages", _) => note(&["packages"], "expected a record".to_string()),
+            ("users", Value::Array(list)) => {
+                for (i, user) in list.into_iter().enumerate() {
+                    let idx = i.to_string();
+                    let Value::Object(fields) = user else {
+                        note(&["users", &idx], "expected a record".to_string());
+                        continue;
+                    };
+                    let mut known = Map::new();
+                    for (field, value) in fields {
+                        if USER_FIELDS.contains(&field.as_str()) {
+                            known.insert(field, value);
+                        } else {
+                            note(&["users", &idx, &field], "no equivalent in horns".to_string());
+                        }
+                    }
+                    match serde_json::from_value::<UserSpec>(Value::Object(known)) {
+                        Ok(spec) => users.push(spec),
+                        Err(e) => note(&["users", &idx], format!("user skipped: {}", e)),
+                    }
+                }
+            }
+            ("users", _) => note(&["users"], "expected a list".to_string()),
+            (key, _) => note(&[key], "no equivalent in horns".to_string()),
+        }
+    }
+
+    // rhino-deploy installed package lists without a managers entry; horns needs one
+    for (id, pkgs) in &by_manager {
+        if !pkgs.is_empty() && !managers.contains_key(id) {
+            managers.insert(id.clone(), enabled_as_deploy(defs, id, true));
+        }
+    }
+
+    notes.sort_by_key(|d| (d.line.unwrap_or(usize::MAX), d.column.unwrap_or(0)));
+    let config = Config {
+        version: migrate::CONFIG_VERSION,
+        package_management: PackageManagement { pre: Vec::new(), post: Vec::new(), managers },
+        custom_packages: CustomPackages { pre: Vec::new(), post: Vec::new(), by_manager },
+        users,
+        software,
+    };
+    Ok((config, notes))
+}
+
+/// How rhino-deploy ran an enabled manager, as a horns spec: it always called `pacstall -PI`,
+/// so pacstall comes over with `noconfirm` on (when the definition still has that flag).
+fn enabled_as_deploy(defs: &ManagerDefs, id: &str, on: bool) -> ManagerSpec {
+    let has_noconfirm = defs
+        .defs
+        .get(id)
+        .and_then(|d| d.others.as_ref())
+        .is_some_and(|o| o.contains_key("noconfirm"));
+    if on && id == "pacstall" && has_noconfirm {
+        ManagerSpec::Object {
+            enabled: true,
+            flags: HashMap::from([("noconfirm".to_string(), Value::Bool(true))]),
+        }
+    } else {
+        ManagerSpec::Bool(on)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::managers::{self, EMBEDDED};
+
+    #[test]
+    fn pacstall_is_imported_with_noconfirm() {
+        let path = std::env::temp_dir().join(format!("horns-import-{}.nuon", std::process::id()));
+        fs::write(
+            &path,
+            "{ packages: { managers: { apt: true }, packages: { pacstall: [nala-deb] } } }",
+        )
+        .unwrap();
+        let defs = managers::from_layers(&[EMBEDDED]).unwrap();
+        let (config, _) = from_nuon(&path, &defs).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let managers = serde_json::to_value(&config.package_management.managers).unwrap();
+        assert_eq!(managers["apt"], Value::Bool(true));
+        assert_eq!(managers["pacstall"], serde_json::json!({ "enabled": true, "noconfirm": true }));
+    }
+}
@@ -1,99 +1,331 @@
-use std::{collections::HashMap, fs, io};
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+use regex::Regex;
 use serde::Deserialize;
 use serde_json::Value;
 
-// import your JSON parsing types and function
-use crate::args::json_to_array::{convert as load_json_config, ManagerSpec};
+use crate::args::diagnostics::{pointer, Diagnostic};
+use crate::args::{formats, schema};
+use crate::args::json_to_array::{Config, HomeSpec, ManagerSpec};
+use crate::args::managers::ManagerDefs;
 
 /// TOML structs (non-binary, library style)
-#[derive(Debug, Deserialize)]
-pub struct TomlConfig {
-    pub package_manager: Vec<TomlPackageManager>,
-}
-
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TomlPackageManager {
     pub id: String,
     pub check: String,                 // presence check command
     pub bootstrap: String,             // install manager if not present
     pub enable: String,                // setup/enabling command (previously "setup")
     pub install: String,               // one-by-one template: "... {{package}}"
-    pub others: Option<HashMap<String, String>>, // flag -> description
+    pub install_batch: Option<String>, // many-at-once template: "... {{packages}}"
+    pub batch_size: Option<usize>,     // max packages per install_batch run
+    pub is_installed: Option<String>,  // exit 0 when {{package}} is already installed
+    pub others: Option<HashMap<String, FlagSpec>>, // flags the config may set
+    pub list: Option<TomlListSpec>,    // query installed packages (backup / drift)
+    pub package_pattern: Option<String>, // regex every package name must fully match
+    #[serde(default)]
+    pub requires: Vec<String>,         // managers that must run (and are enabled) before this one
+    #[serde(default)]
+    pub after: Vec<String>,            // managers that run first when they're enabled too
+    #[serde(default)]
+    pub locks: Vec<String>,            // named locks held for the whole run; no two holders run at once
+    #[serde(default)]
+    pub bootstrap_locks: Vec<String>,  // like locks, but released once the manager is usable
+    #[serde(skip)]
+    compiled_pattern: OnceLock<Regex>, // anchored package_pattern, compiled by validate()
 }
 
-/// Load Packages.toml into a map id -> definition
-pub fn load_toml_defs(path: &str) -> Result<HashMap<String, TomlPackageManager>, io::Error> {
-    let toml_str = fs::read_to_string(path)?;
-    let cfg: TomlConfig = toml::from_str(&toml_str)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("TOML parse error: {e}")))?;
-    Ok(cfg.package_manager.into_iter().map(|pm| (pm.id.clone(), pm)).collect())
+/// A manager flag from `others`: either a bare description (any scalar value, the old form)
+/// or a typed declaration.
+#[derive(Debug, Clone)]
+pub enum FlagSpec {
+    Description(String),
+    Typed(TypedFlag),
 }
 
-/// Strict comparison: returns Ok(report) when valid, Err(message) on the first validation failure.
-pub fn compare_and_report(json_path: &str, toml_path: &str) -> Result<String, String> {
-    // load TOML defs
-    let toml_defs = load_toml_defs(toml_path).map_err(|e| format!("Failed to load TOML: {}", e))?;
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TypedFlag {
+    #[serde(rename = "type")]
+    pub kind: FlagType,
+    #[serde(default)]
+    pub description: String,
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub values: Vec<String>,           // allowed values of an enum flag
+    // What the flag does while it's on (true, or any other non-empty value)
+    pub before: Option<String>,        // run once before the manager's installs
+    pub args: Option<String>,          // extra install arguments, in front of {{package}} or at {{args}}
+    pub after: Option<String>,         // run once after the manager's installs
+}
 
-    // load JSON config using your existing convert (keeps error messages as io::Error)
-    let json_cfg = load_json_config(json_path).map_err(|e| format!("Failed to load JSON: {}", e))?;
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlagType {
+    Bool,
+    String,
+    Int,
+    Enum,
+}
 
-    // Validate managers in package_management
-    for (json_id, spec) in &json_cfg.package_management.managers {
-        // strict: id must exist in TOML exactly
-        let def = toml_defs.get(json_id).ok_or_else(|| {
-            format!("Unknown manager '{}' in JSON; not defined in Packages.toml", json_id)
+// By hand so a typo in a typed flag reports the field instead of "no variant matched"
+impl<'de> Deserialize<'de> for FlagSpec {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        match Value::deserialize(d)? {
+            Value::String(s) => Ok(FlagSpec::Description(s)),
+            v @ Value::Object(_) => serde_json::from_value(v)
+                .map(FlagSpec::Typed)
+                .map_err(serde::de::Error::custom),
+            other => Err(serde::de::Error::custom(format!(
+                "a flag is a description string or a table with a type, found {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl FlagSpec {
+    pub fn description(&self) -> &str {
+        match self {
+            FlagSpec::Description(d) => d,
+            FlagSpec::Typed(t) => &t.description,
+        }
+    }
+
+    pub fn default(&self) -> Option<&Value> {
+        match self {
+            FlagSpec::Typed(t) => t.default.as_ref(),
+            FlagSpec::Description(_) => None,
+        }
+    }
+
+    pub fn required(&self) -> bool {
+        matches!(self, FlagSpec::Typed(t) if t.required)
+    }
+
+    /// Why `value` isn't acceptable for this flag, if it isn't.
+    pub fn check_value(&self, value: &Value) -> Result<(), String> {
+        let FlagSpec::Typed(t) = self else {
+            // untyped flags take any scalar
+            return match value {
+                Value::Array(_) | Value::Object(_) => Err("must be a string, number or boolean".to_string()),
+                _ => Ok(()),
+            };
+        };
+        match (t.kind, value) {
+            (FlagType::Bool, Value::Bool(_)) => Ok(()),
+            (FlagType::String, Value::String(_)) => Ok(()),
+            (FlagType::Int, Value::Number(n)) if n.is_i64() || n.is_u64() => Ok(()),
+            (FlagType::Enum, Value::String(s)) if t.values.contains(s) => Ok(()),
+            (FlagType::Enum, _) => Err(format!("must be one of {}", t.values.join(", "))),
+            (FlagType::Bool, _) => Err("must be true or false".to_string()),
+            (FlagType::String, _) => Err("must be a string".to_string()),
+            (FlagType::Int, _) => Err("must be an integer".to_string()),
+        }
+    }
+
+    /// The flag's hooks when `value` switches it on: `true`, or any non-empty, non-false value.
+    pub fn hooks(&self, value: &Value) -> Option<&TypedFlag> {
+        let FlagSpec::Typed(t) = self else { return None };
+        let on = match value {
+            Value::Bool(b) => *b,
+            Value::Null => false,
+            Value::String(s) => !s.is_empty(),
+            _ => true,
+        };
+        on.then_some(t)
+    }
+
+    /// Short form for listings, e.g. `bool, default false`.
+    pub fn summary(&self) -> String {
+        let FlagSpec::Typed(t) = self else {
+            return "any".to_string();
+        };
+        let mut out = match t.kind {
+            FlagType::Enum => format!("enum: {}", t.values.join("|")),
+            kind => format!("{:?}", kind).to_lowercase(),
+        };
+        if let Some(default) = &t.default {
+            out.push_str(&format!(", default {}", default));
+        }
+        if t.required {
+            out.push_str(", required");
+        }
+        out
+    }
+}
+
+/// Used when a manager has no `package_pattern`: plain names, versions, arches and paths,
+/// but nothing the shell would treat specially.
+pub const DEFAULT_PACKAGE_PATTERN: &str = r"[A-Za-z0-9][A-Za-z0-9._+:@/=~-]*";
+
+/// Packages per install_batch run when a manager doesn't set `batch_size`.
+pub const DEFAULT_BATCH_SIZE: usize = 50;
+
+/// User and group names useradd/groupadd accept.
+pub const ACCOUNT_NAME: &str = r"^[a-z_][a-z0-9_-]{0,31}$";
+
+impl TomlPackageManager {
+    /// The package pattern, anchored so it has to match the whole name.
+    pub fn anchored_pattern(&self) -> String {
+        let pat = self.package_pattern.as_deref().unwrap_or(DEFAULT_PACKAGE_PATTERN);
+        format!("^(?:{})$", pat)
+    }
+
+    /// The compiled anchored pattern; built on first use (normally by `validate`) and kept.
+    fn package_regex(&self) -> Result<&Regex, String> {
+        if let Some(re) = self.compiled_pattern.get() {
+            return Ok(re);
+        }
+        let re = Regex::new(&self.anchored_pattern()).map_err(|e| {
+            let pat = self.package_pattern.as_deref().unwrap_or(DEFAULT_PACKAGE_PATTERN);
+            format!("Manager '{}': invalid package_pattern '{}': {}", self.id, pat, e)
         })?;
+        Ok(self.compiled_pattern.get_or_init(|| re))
+    }
 
-        match spec {
-            ManagerSpec::Bool(_) => {
-                // ok: boolean form uses no flags
-            }
-            ManagerSpec::Object { enabled: _, flags } => {
-                if let Some(allowed) = &def.others {
-                    for (flag, value) in flags {
-                        if !allowed.contains_key(flag) {
-                            return Err(format!(
-                                "Unknown flag '{}' for manager '{}' in JSON; not defined in Packages.toml",
-                                flag, json_id
-                            ));
-                        }
-                        // optional: disallow complex types for flags
-                        match value {
-                            Value::Bool(_) | Value::Number(_) | Value::String(_) | Value::Null => {}
-                            Value::Array(_) | Value::Object(_) => {
-                                return Err(format!(
-                                    "Unsupported complex value for flag '{}' on '{}': {}",
-                                    flag, json_id, value
-                                ));
-                            }
-                        }
-                    }
-                } else if !flags.is_empty() {
-                    let unknowns = flags.keys().cloned().collect::<Vec<_>>().join(", ");
-                    return Err(format!(
-                        "Manager '{}' does not support flags (TOML has no 'others'), but JSON provided: {}",
-                        json_id, unknowns
-                    ));
-                }
+    /// Reject package names that don't match this manager's pattern.
+    pub fn check_package(&self, name: &str) -> Result<(), String> {
+        if self.package_regex()?.is_match(name) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Package '{}' for manager '{}' does not match its package_pattern",
+                name, self.id
+            ))
+        }
+    }
+}
+
+/// A named bundle from the `[[software]]` catalog, e.g. `nala` → pacstall `nala-deb`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SoftwarePreset {
+    pub id: String,
+    pub manager: String,               // manager id the packages install through
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub post: Vec<String>,             // shell steps after the packages are installed
+}
+
+/// How to ask a manager what is installed and how to read its output.
+/// With neither `regex` nor `column`, every non-empty line is a package name.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TomlListSpec {
+    pub command: String,               // prints installed packages
+    #[serde(default)]
+    pub skip: usize,                   // header lines to drop before parsing
+    pub regex: Option<String>,         // line regex; capture group 1 (or the whole match) is the name
+    pub column: Option<usize>,         // 0-based whitespace-separated column holding the name
+}
+
+impl TomlListSpec {
+    /// Parse the list command's stdout into sorted, de-duplicated package names.
+    pub fn parse(&self, stdout: &str) -> Result<Vec<String>, String> {
+        let re = match &self.regex {
+            Some(r) => Some(Regex::new(r).map_err(|e| format!("invalid list regex '{}': {}", r, e))?),
+            None => None,
+        };
+
+        let mut pkgs: Vec<String> = Vec::new();
+        for line in stdout.lines().skip(self.skip) {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let name = if let Some(re) = &re {
+                // lines that don't match are noise (headers, separators, ...)
+                let Some(caps) = re.captures(line) else { continue };
+                caps.get(1).or_else(|| caps.get(0)).map(|m| m.as_str())
+            } else if let Some(col) = self.column {
+                line.split_whitespace().nth(col)
+            } else {
+                Some(line)
+            };
+            if let Some(name) = name.map(str::trim).filter(|n| !n.is_empty()) {
+                pkgs.push(name.to_string());
             }
         }
+        pkgs.sort();
+        pkgs.dedup();
+        Ok(pkgs)
     }
+}
 
-    // Validate custom_packages keys reference known managers
-    for mgr in json_cfg.custom_packages.by_manager.keys() {
-        if !toml_defs.contains_key(mgr) {
-            return Err(format!(
-                "Unknown custom_packages key '{}' in JSON; must be defined in Packages.toml",
-                mgr
-            ));
+impl TomlPackageManager {
+    /// Packages per install_batch run.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE)
+    }
+
+    /// Checks that don't fit serde: regexes compile, list options don't conflict, the batch template has its list.
+    pub fn validate(&self) -> Result<(), String> {
+        self.package_regex()?;
+        if let Some(query) = &self.is_installed
+            && !query.contains("{{package}}")
+            && !query.contains("{{!package}}")
+        {
+            return Err(format!("Manager '{}': is_installed has no {{{{package}}}} placeholder", self.id));
+        }
+        if let Some(batch) = &self.install_batch
+            && !batch.contains("{{packages}}")
+            && !batch.contains("{{!packages}}")
+        {
+            return Err(format!("Manager '{}': install_batch has no {{{{packages}}}} placeholder", self.id));
+        }
+        if self.batch_size == Some(0) {
+            return Err(format!("Manager '{}': batch_size must be at least 1", self.id));
+        }
+        for (name, flag) in self.others.iter().flatten() {
+            let FlagSpec::Typed(t) = flag else { continue };
+            let bad = |msg: String| Err(format!("Manager '{}': flag '{}' {}", self.id, name, msg));
+            if t.kind == FlagType::Enum && t.values.is_empty() {
+                return bad("is an enum without 'values'".to_string());
+            }
+            if t.kind != FlagType::Enum && !t.values.is_empty() {
+                return bad("has 'values' but is not an enum".to_string());
+            }
+            if t.required && t.default.is_some() {
+                return bad("is required and has a default; use one or the other".to_string());
+            }
+            if let Some(default) = &t.default
+                && let Err(e) = flag.check_value(default)
+            {
+                return bad(format!("has an invalid default {}: {}", default, e));
+            }
+        }
+        if let Some(list) = &self.list {
+            if list.regex.is_some() && list.column.is_some() {
+                return Err(format!(
+                    "Manager '{}': list accepts either 'regex' or 'column', not both",
+                    self.id
+                ));
+            }
+            if let Some(r) = &list.regex {
+                Regex::new(r)
+                    .map_err(|e| format!("Manager '{}': invalid list regex '{}': {}", self.id, r, e))?;
+            }
         }
+        Ok(())
     }
+}
 
-    // Build a short report string
+/// One block per manager definition, sorted by id. Ids found in `origins` are annotated with it.
+pub fn describe_defs(
+    toml_defs: &HashMap<String, TomlPackageManager>,
+    origins: &HashMap<String, String>,
+) -> String {
     let mut rpt = String::new();
-    rpt.push_str("Validation OK\n\nDefined package managers (TOML):\n");
-    for (id, def) in &toml_defs {
-        rpt.push_str(&format!(" - {}\n", id));
+    let mut ids: Vec<&String> = toml_defs.keys().collect();
+    ids.sort();
+    for (id, def) in ids.into_iter().map(|id| (id, &toml_defs[id])) {
+        match origins.get(id) {
+            Some(origin) => rpt.push_str(&format!(" - {} (from {})\n", id, origin)),
+            None => rpt.push_str(&format!(" - {}\n", id)),
+        }
         rpt.push_str(&format!("    check: {}\n", def.check));
         if !def.bootstrap.trim().is_empty() {
             rpt.push_str(&format!("    bootstrap: {}\n", def.bootstrap));
@@ -102,15 +334,279 @@ pub fn compare_and_report(json_path: &str, toml_path: &str) -> Result<String, St
             rpt.push_str(&format!("    enable: {}\n", def.enable));
         }
         rpt.push_str(&format!("    install: {}\n", def.install));
-        if let Some(others) = &def.others {
-            if !others.is_empty() {
-                let keys = others.keys().cloned().collect::<Vec<_>>().join(", ");
-                rpt.push_str(&format!("    flags: {}\n", keys));
+        if let Some(batch) = &def.install_batch {
+            rpt.push_str(&format!("    install_batch: {} (up to {} at a time)\n", batch, def.batch_size()));
+        }
+        if !def.requires.is_empty() {
+            rpt.push_str(&format!("    requires: {}\n", def.requires.join(", ")));
+        }
+        if !def.after.is_empty() {
+            rpt.push_str(&format!("    after: {}\n", def.after.join(", ")));
+        }
+        if !def.locks.is_empty() {
+            rpt.push_str(&format!("    locks: {}\n", def.locks.join(", ")));
+        }
+        if !def.bootstrap_locks.is_empty() {
+            rpt.push_str(&format!("    bootstrap_locks: {}\n", def.bootstrap_locks.join(", ")));
+        }
+        if let Some(query) = &def.is_installed {
+            rpt.push_str(&format!("    is_installed: {}\n", query));
+        }
+        if let Some(list) = &def.list {
+            rpt.push_str(&format!("    list: {}\n", list.command));
+        }
+        if let Some(others) = &def.others
+            && !others.is_empty()
+        {
+            let mut flags: Vec<(&String, &FlagSpec)> = others.iter().collect();
+            flags.sort_by_key(|(name, _)| *name);
+            for (name, flag) in flags {
+                rpt.push_str(&format!("    flag {} ({})", name, flag.summary()));
+                match flag.description() {
+                    "" => rpt.push('\n'),
+                    d => rpt.push_str(&format!(": {}\n", d)),
+                }
+                if let FlagSpec::Typed(t) = flag {
+                    let hooks = [("before", &t.before), ("args", &t.args), ("after", &t.after)];
+                    for (what, cmd) in hooks {
+                        if let Some(cmd) = cmd {
+                            rpt.push_str(&format!("      {}: {}\n", what, cmd));
+                        }
+                    }
+                }
             }
         }
     }
+    rpt
+}
 
-    rpt.push_str("\nJSON snapshot (parsed):\n");
+/// One line per software preset, sorted by id, plus its post steps.
+pub fn describe_software(defs: &ManagerDefs) -> String {
+    let mut rpt = String::new();
+    let mut ids: Vec<&String> = defs.software.keys().collect();
+    ids.sort();
+    for id in ids {
+        let preset = &defs.software[id];
+        rpt.push_str(&format!(
+            " - {}: {} via {}",
+            id,
+            preset.packages.join(", "),
+            preset.manager
+        ));
+        match defs.software_origins.get(id) {
+            Some(origin) => rpt.push_str(&format!(" (from {})\n", origin)),
+            None => rpt.push('\n'),
+        }
+        for step in &preset.post {
+            rpt.push_str(&format!("    post: {}\n", step));
+        }
+    }
+    rpt
+}
+
+/// Strict comparison: returns Ok((config, report)) when valid, Err(diagnostics) listing every validation failure.
+pub fn compare_and_report(
+    json_path: &Path,
+    defs: &ManagerDefs,
+) -> Result<(Config, String), Vec<Diagnostic>> {
+    let toml_defs = &defs.defs;
+    // parse here rather than via convert so schema errors keep their location
+    let (json_cfg, raw, positions) =
+        formats::read_config(json_path).map_err(|d| vec![d])?;
+    let mut diags: Vec<Diagnostic> = Vec::new();
+
+    // Validate managers in package_management
+    for (json_id, spec) in &json_cfg.package_management.managers {
+        // strict: id must exist in TOML exactly
+        let Some(def) = toml_defs.get(json_id) else {
+            diags.push(Diagnostic::at(
+                &positions,
+                pointer(&["package_management", json_id]),
+                format!("Unknown manager '{}' in config; not defined in Packages.toml", json_id),
+            ));
+            continue;
+        };
+
+        let (enabled, flags) = match spec {
+            ManagerSpec::Bool(b) => (*b, None),
+            ManagerSpec::Object { enabled, flags } => (*enabled, Some(flags)),
+        };
+        for (flag, value) in flags.into_iter().flatten() {
+            let at = pointer(&["package_management", json_id, flag]);
+            match def.others.as_ref().map(|others| others.get(flag)) {
+                None => {
+                    diags.push(Diagnostic::at(
+                        &positions,
+                        at,
+                        format!(
+                            "Manager '{}' does not support flags (TOML has no 'others'), but the config provided '{}'",
+                            json_id, flag
+                        ),
+                    ));
+                }
+                Some(None) => {
+                    diags.push(Diagnostic::at(
+                        &positions,
+                        at,
+                        format!(
+                            "Unknown flag '{}' for manager '{}' in config; not defined in Packages.toml",
+                            flag, json_id
+                        ),
+                    ));
+                }
+                Some(Some(declared)) => {
+                    if let Err(e) = declared.check_value(value) {
+                        diags.push(Diagnostic::at(
+                            &positions,
+                            at,
+                            format!("Flag '{}' for manager '{}' {}, found {}", flag, json_id, e, value),
+                        ));
+                    }
+                }
+            }
+        }
+
+        // Required flags only matter when the manager is going to run
+        if enabled {
+            let mut missing: Vec<&String> = def
+                .others
+                .iter()
+                .flatten()
+                .filter(|(name, f)| f.required() && !flags.is_some_and(|given| given.contains_key(*name)))
+                .map(|(name, _)| name)
+                .collect();
+            missing.sort();
+            for name in missing {
+                diags.push(Diagnostic::at(
+                    &positions,
+                    pointer(&["package_management", json_id]),
+                    format!("Manager '{}' needs flag '{}' (required in Packages.toml)", json_id, name),
+                ));
+            }
+        }
+    }
+
+    // Validate custom_packages keys reference known managers, and their package names
+    for (mgr, pkgs) in &json_cfg.custom_packages.by_manager {
+        let Some(def) = toml_defs.get(mgr) else {
+            diags.push(Diagnostic::at(
+                &positions,
+                pointer(&["custom_packages", mgr]),
+                format!(
+                    "Unknown custom_packages key '{}' in config; must be defined in Packages.toml",
+                    mgr
+                ),
+            ));
+            continue;
+        };
+        for (i, pkg) in pkgs.iter().enumerate() {
+            if let Err(e) = def.check_package(pkg) {
+                diags.push(Diagnostic::at(&positions, pointer(&["custom_packages", mgr, &i.to_string()]), e));
+            }
+        }
+    }
+
+    // Validate software presets: known ids, and not routed through a manager the config turns off
+    for (id, wanted) in &json_cfg.software {
+        let at = pointer(&["software", id]);
+        let Some(preset) = defs.software.get(id) else {
+            diags.push(Diagnostic::at(
+                &positions,
+                at,
+                format!("Unknown software preset '{}' in config; not defined in Packages.toml", id),
+            ));
+            continue;
+        };
+        let manager_off = match json_cfg.package_management.managers.get(&preset.manager) {
+            Some(ManagerSpec::Bool(b)) => !b,
+            Some(ManagerSpec::Object { enabled, .. }) => !enabled,
+            None => false,
+        };
+        if *wanted && manager_off {
+            diags.push(Diagnostic::at(
+                &positions,
+                at,
+                format!(
+                    "Software preset '{}' installs through '{}', which is disabled in package_management",
+                    id, preset.manager
+                ),
+            ));
+        }
+    }
+
+    // Validate users: names/groups useradd accepts, absolute homes, no duplicates
+    let account_name = Regex::new(ACCOUNT_NAME).expect("account name regex");
+    let mut seen_users: Vec<&str> = Vec::new();
+    for (i, user) in json_cfg.users.iter().enumerate() {
+        let idx = i.to_string();
+        if !account_name.is_match(&user.name) {
+            diags.push(Diagnostic::at(
+                &positions,
+                pointer(&["users", &idx, "name"]),
+                format!("Invalid user name '{}'", user.name),
+            ));
+        } else if seen_users.contains(&user.name.as_str()) {
+            diags.push(Diagnostic::at(
+                &positions,
+                pointer(&["users", &idx, "name"]),
+                format!("User '{}' is listed more than once", user.name),
+            ));
+        }
+        seen_users.push(&user.name);
+        for (j, group) in user.groups.iter().enumerate() {
+            if !account_name.is_match(group) {
+                diags.push(Diagnostic::at(
+                    &positions,
+                    pointer(&["users", &idx, "groups", &j.to_string()]),
+                    format!("Invalid group name '{}' for user '{}'", group, user.name),
+                ));
+            }
+        }
+        if let HomeSpec::Path(home) = &user.home
+            && !home.starts_with('/')
+        {
+            diags.push(Diagnostic::at(
+                &positions,
+                pointer(&["users", &idx, "home"]),
+                format!("Home '{}' for user '{}' must be an absolute path", home, user.name),
+            ));
+        }
+        if let Some(comment) = &user.comment
+            && comment.contains([':', '\n'])
+        {
+            diags.push(Diagnostic::at(
+                &positions,
+                pointer(&["users", &idx, "comment"]),
+                format!("Comment for user '{}' may not contain ':' or newlines", user.name),
+            ));
+        }
+    }
+
+    // Schema errors last: where a check above already explains the same spot, keep only that
+    let related = |a: &str, b: &str| a == b || a.starts_with(&format!("{}/", b)) || b.starts_with(&format!("{}/", a));
+    match schema::check(&raw, &schema::config_schema(defs), &positions) {
+        Ok(schema_diags) => {
+            for d in schema_diags {
+                if d.pointer.is_empty() || !diags.iter().any(|o| related(&o.pointer, &d.pointer)) {
+                    diags.push(d);
+                }
+            }
+        }
+        Err(e) => eprintln!("(warn) skipping schema validation, the schema does not compile: {}", e),
+    }
+
+    if !diags.is_empty() {
+        // Report in file order
+        diags.sort_by_key(|d| (d.line.unwrap_or(usize::MAX), d.column.unwrap_or(0), d.pointer.clone()));
+        return Err(diags);
+    }
+
+    // Build a short report string
+    let mut rpt = String::new();
+    rpt.push_str("Validation OK\n\nDefined package managers (TOML):\n");
+    rpt.push_str(&describe_defs(toml_defs, &HashMap::new()));
+
+    rpt.push_str("\nConfig snapshot (parsed):\n");
     // show json managers and any flags used
     for (id, spec) in &json_cfg.package_management.managers {
         match spec {
@@ -130,6 +626,31 @@ pub fn compare_and_report(json_path: &str, toml_path: &str) -> Result<String, St
         }
     }
 
+    if !json_cfg.users.is_empty() {
+        rpt.push_str("\nUsers:\n");
+        for user in &json_cfg.users {
+            let groups = user.groups.join(", ");
+            rpt.push_str(&format!(" - {} (root: {}, groups: {})\n", user.name, user.root, groups));
+        }
+    }
+
+    if !json_cfg.software.is_empty() {
+        rpt.push_str("\nSoftware presets:\n");
+        let mut ids: Vec<&String> = json_cfg.software.keys().collect();
+        ids.sort();
+        for id in ids {
+            let preset = &defs.software[id];
+            let state = if json_cfg.software[id] { "" } else { " (off)" };
+            rpt.push_str(&format!(
+                " - {}{}: {} via {}\n",
+                id,
+                state,
+                preset.packages.join(", "),
+                preset.manager
+            ));
+        }
+    }
+
     // custom packages listing
     rpt.push_str("\nCustom packages by manager:\n");
     for (mgr, list) in &json_cfg.custom_packages.by_manager {
@@ -140,5 +661,144 @@ pub fn compare_and_report(json_path: &str, toml_path: &str) -> Result<String, St
         }
     }
 
-    Ok(rpt)
+    Ok((json_cfg, rpt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::managers;
+    use serde_json::json;
+    use std::fs;
+
+    /// Parse one `[[package_manager]]` entry and run its `validate`.
+    fn manager(toml: &str) -> Result<TomlPackageManager, String> {
+        let text = format!("id = \"fake\"\ncheck = \"\"\nbootstrap = \"\"\nenable = \"\"\n{}", toml);
+        let def: TomlPackageManager = toml::from_str(&text).map_err(|e| e.to_string())?;
+        def.validate()?;
+        Ok(def)
+    }
+
+    #[test]
+    fn package_names_must_match_the_pattern() {
+        let def = manager("install = \"fake {{package}}\"").unwrap();
+        assert!(def.check_package("curl").is_ok());
+        assert!(def.check_package("lib32-foo:i386=1.2~3").is_ok());
+        for bad in ["foo; rm -rf ~", "$(id)", "a b", "`x`", "-rf", ""] {
+            assert!(def.check_package(bad).is_err(), "{:?} was accepted", bad);
+        }
+
+        // A manager's own pattern has to match the whole name
+        let def = manager("install = \"fake {{package}}\"\npackage_pattern = '[a-z]+'").unwrap();
+        assert!(def.check_package("abc").is_ok());
+        assert!(def.check_package("abc1").is_err());
+        let err = manager("install = \"fake {{package}}\"\npackage_pattern = '('").unwrap_err();
+        assert!(err.contains("invalid package_pattern"), "{}", err);
+    }
+
+    fn list(toml: &str) -> TomlListSpec {
+        toml::from_str(&format!("command = \"list\"\n{}", toml)).unwrap()
+    }
+
+    #[test]
+    fn list_output_skips_headers_and_picks_a_column() {
+        let snap = "Name    Version   Rev    Tracking       Publisher   Notes\n\
+                    core22  20240111  1122   latest/stable  canonical✓  base\n\
+                    firefox 124.0-2   4090   latest/stable  mozilla✓    -\n\
+                    \n\
+                    core22  20240111  1122   latest/stable  canonical✓  base\n";
+        let spec = list("skip = 1\ncolumn = 0");
+        assert_eq!(spec.parse(snap).unwrap(), ["core22", "firefox"]);
+        // Without the skip the header's first column would be taken for a package
+        assert_eq!(list("column = 0").parse(snap).unwrap(), ["Name", "core22", "firefox"]);
+    }
+
+    #[test]
+    fn list_regex_takes_its_first_group_or_the_whole_match() {
+        let out = "ii  curl  8.5.0\nrc  gone  1.0\nii  vim  9.1\n";
+        let spec = list(r#"regex = '^ii\s+(\S+)'"#);
+        assert_eq!(spec.parse(out).unwrap(), ["curl", "vim"]);
+
+        let spec = list(r#"regex = '[a-z]+\.[a-z]+\.[A-Za-z]+'"#);
+        let out = "org.gnome.Maps\tstable\nnoise\ncom.spotify.Client\tstable\n";
+        assert_eq!(spec.parse(out).unwrap(), ["com.spotify.Client", "org.gnome.Maps"]);
+
+        let err = list("regex = '('").parse("x").unwrap_err();
+        assert!(err.contains("invalid list regex"), "{}", err);
+    }
+
+    #[test]
+    fn typed_flag_definitions_are_checked() {
+        let ok = manager(
+            "install = \"fake {{package}}\"\n\
+             others.update = { type = \"bool\", default = false }\n\
+             others.mode = { type = \"enum\", values = [\"fast\", \"safe\"], default = \"safe\" }",
+        );
+        assert!(ok.is_ok(), "{:?}", ok.err());
+
+        let err = manager("install = \"fake {{package}}\"\nothers.channel = { type = \"string\", required = true, default = \"stable\" }").unwrap_err();
+        assert!(err.contains("flag 'channel' is required and has a default"), "{}", err);
+        let err = manager("install = \"fake {{package}}\"\nothers.update = { type = \"bool\", default = \"yes\" }").unwrap_err();
+        assert!(err.contains("flag 'update' has an invalid default \"yes\": must be true or false"), "{}", err);
+        let err = manager("install = \"fake {{package}}\"\nothers.mode = { type = \"enum\", values = [\"fast\", \"safe\"], default = \"slow\" }")
+            .unwrap_err();
+        assert!(err.contains("flag 'mode' has an invalid default \"slow\""), "{}", err);
+    }
+
+    const FLAGGED: &str = r#"
+[[package_manager]]
+id = "fake"
+check = ""
+bootstrap = ""
+enable = ""
+install = "fake {{package}}"
+others.update = { type = "bool", default = false }
+others.mode = { type = "enum", values = ["fast", "safe"], default = "safe" }
+others.channel = { type = "string", required = true }
+"#;
+
+    /// Validate a config against FLAGGED and return the diagnostic messages.
+    fn diagnose(name: &str, config: Value) -> Vec<String> {
+        let defs = managers::from_layers(&[FLAGGED]).unwrap();
+        let path = std::env::temp_dir().join(format!("horns-flags-{}-{}.json", name, std::process::id()));
+        fs::write(&path, config.to_string()).unwrap();
+        let result = compare_and_report(&path, &defs);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Ok(_) => Vec::new(),
+            Err(diags) => diags.into_iter().map(|d| d.message).collect(),
+        }
+    }
+
+    fn with_flags(fake: Value) -> Value {
+        json!({
+            "version": 1,
+            "package_management": { "pre": [], "post": [], "fake": fake },
+            "custom_packages": { "pre": [], "post": [] },
+        })
+    }
+
+    #[test]
+    fn config_flag_values_must_match_their_type() {
+        let good = with_flags(json!({ "enabled": true, "channel": "stable", "update": true, "mode": "fast" }));
+        assert_eq!(diagnose("good", good), Vec::<String>::new());
+
+        let msgs = diagnose("bool", with_flags(json!({ "enabled": true, "channel": "stable", "update": "yes" })));
+        assert_eq!(msgs.len(), 1, "{:?}", msgs);
+        assert!(msgs[0].contains("Flag 'update' for manager 'fake' must be true or false"), "{}", msgs[0]);
+
+        let msgs = diagnose("enum", with_flags(json!({ "enabled": true, "channel": "stable", "mode": "slow" })));
+        assert_eq!(msgs.len(), 1, "{:?}", msgs);
+        assert!(msgs[0].contains("Flag 'mode' for manager 'fake' must be one of"), "{}", msgs[0]);
+    }
+
+    #[test]
+    fn required_flags_are_only_needed_when_enabled() {
+        let msgs = diagnose("missing", with_flags(json!({ "enabled": true, "update": true })));
+        assert_eq!(msgs, vec!["Manager 'fake' needs flag 'channel' (required in Packages.toml)".to_string()]);
+        assert_eq!(diagnose("bare", with_flags(json!(true))).len(), 1);
+
+        assert_eq!(diagnose("disabled", with_flags(json!({ "enabled": false }))), Vec::<String>::new());
+        assert_eq!(diagnose("off", with_flags(json!(false))), Vec::<String>::new());
+    }
 }
@@ -1,20 +1,29 @@
 // json_to_array.rs
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::{collections::HashMap, fs, io};
+use std::collections::HashMap;
 
-#[path = "./terminal.rs"]
-mod terminal;
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
+    // Layout version, see migrate.rs; older documents are upgraded before they get here
+    pub version: u64,
+
     pub package_management: PackageManagement,
     pub custom_packages: CustomPackages,
+
+    // Local users to create or update (same shape as the `users` list in deploy.nuon)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<UserSpec>,
+
+    // Presets from the [[software]] catalog, e.g. { "nala": true }
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub software: HashMap<String, bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 // Do NOT deny unknown fields here, we capture them via #[serde(flatten)]
 pub struct PackageManagement {
     pub pre: Vec<String>,
@@ -27,7 +36,7 @@ pub struct PackageManagement {
 }
 
 // Managers can be either a simple boolean or an object with "enabled" and arbitrary flags.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum ManagerSpec {
     Bool(bool),
@@ -38,7 +47,7 @@ pub enum ManagerSpec {
     },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 // Do NOT deny unknown fields here, we capture them via #[serde(flatten)]
 pub struct CustomPackages {
     pub pre: Vec<String>,
@@ -49,18 +58,34 @@ pub struct CustomPackages {
     pub by_manager: HashMap<String, Vec<String>>,
 }
 
-pub fn convert(path: &str) -> Result<Config, io::Error> {
-    let json = fs::read_to_string(path)?;
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UserSpec {
+    pub name: String,
+    #[serde(default)]
+    pub home: HomeSpec,
+    // Member of the sudo group
+    #[serde(default)]
+    pub root: bool,
+    // Prompt for a password if the account has none yet
+    #[serde(default)]
+    pub password: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+// `home: true` creates /home/<name>, `false` creates no home, a string is the home path.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum HomeSpec {
+    Create(bool),
+    Path(String),
+}
 
-    match serde_json::from_str::<Config>(&json) {
-        Ok(config) => {
-            println!("{:#?}", config); // print the parsed Config
-            Ok(config)
-        }
-        Err(err) => {
-            let msg = format!("❌ JSON schema error at column {}: {}", err.column(), err);
-            eprintln!("{}", msg);
-            Err(io::Error::new(io::ErrorKind::InvalidData, msg))
-        }
+impl Default for HomeSpec {
+    fn default() -> Self {
+        HomeSpec::Create(true)
     }
 }
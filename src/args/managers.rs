@@ -0,0 +1,456 @@
+// managers.rs
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+use crate::args::json_and_config::{SoftwarePreset, TomlPackageManager};
+
+/// Compiled-in copy of Packages.toml, the bottom layer of every lookup.
+pub const EMBEDDED: &str = include_str!("Packages.toml");
+
+pub const SYSTEM_FILE: &str = "/etc/horns/Packages.toml";
+
+/// Merged manager definitions and software presets, and the layer(s) each one came from.
+#[derive(Debug, Default)]
+pub struct ManagerDefs {
+    pub defs: HashMap<String, TomlPackageManager>,
+    pub origins: HashMap<String, String>,
+    pub disabled: HashMap<String, String>, // id -> layer that disabled it
+    pub software: HashMap<String, SoftwarePreset>,
+    pub software_origins: HashMap<String, String>,
+    pub disabled_software: HashMap<String, String>,
+    pub layers: Vec<String>, // every layer that was read, lowest first
+}
+
+/// `$XDG_CONFIG_HOME/horns`, falling back to `~/.config/horns`.
+pub fn user_config_dir() -> Option<PathBuf> {
+    if let Some(xdg) = env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(xdg).join("horns"));
+    }
+    env::var_os("HOME")
+        .filter(|v| !v.is_empty())
+        .map(|home| PathBuf::from(home).join(".config/horns"))
+}
+
+/// Layer files above the embedded copy, lowest priority first.
+/// `config_dir` replaces the user config directory; `managers` is an explicit extra file.
+pub fn search_path(config_dir: Option<&Path>, managers: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(SYSTEM_FILE)];
+    let user_dir = config_dir.map(Path::to_path_buf).or_else(user_config_dir);
+    if let Some(dir) = user_dir {
+        paths.push(dir.join("Packages.toml"));
+    }
+    if let Some(file) = managers {
+        paths.push(file.to_path_buf());
+    }
+    paths
+}
+
+/// Load the embedded definitions and overlay every file on the search path that exists.
+/// Only an explicit `managers` file has to exist.
+///
+/// Layers merge per field (for `[[package_manager]]` and `[[software]]` alike): a later entry with an existing id only needs the
+/// keys it changes (tables such as `others` merge key by key), a new id must be complete,
+/// and `disabled = true` removes an id defined by an earlier layer.
+pub fn resolve(
+    config_dir: Option<&Path>,
+    managers: Option<&Path>,
+) -> Result<ManagerDefs, io::Error> {
+    let mut merged = Merge::default();
+    merged.overlay(EMBEDDED, "built-in")?;
+
+    for path in search_path(config_dir, managers) {
+        let explicit = managers == Some(path.as_path());
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => continue,
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Cannot read manager definitions {}: {}", path.display(), e),
+                ));
+            }
+        };
+        merged.overlay(&text, &path.display().to_string())?;
+    }
+
+    merged.finish()
+}
+
+/// Definitions from the given layer texts only, lowest priority first; nothing is read from disk.
+#[cfg(test)]
+pub fn from_layers(layers: &[&str]) -> Result<ManagerDefs, io::Error> {
+    let mut merged = Merge::default();
+    for (i, text) in layers.iter().enumerate() {
+        merged.overlay(text, &format!("layer {}", i))?;
+    }
+    merged.finish()
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The array-of-tables sections a layer may contain, and the noun used in errors.
+const SECTIONS: [(&str, &str); 2] = [("package_manager", "manager"), ("software", "software preset")];
+
+/// Raw TOML tables per id for one section while layers are being applied.
+#[derive(Default)]
+struct Section {
+    tables: BTreeMap<String, (Table, Vec<String>)>, // id -> (merged table, contributing layers)
+    disabled: HashMap<String, String>,
+}
+
+#[derive(Default)]
+struct Merge {
+    sections: HashMap<&'static str, Section>,
+    layers: Vec<String>,
+}
+
+impl Merge {
+    fn overlay(&mut self, text: &str, origin: &str) -> Result<(), io::Error> {
+        let mut doc: Table = toml::from_str(text)
+            .map_err(|e| invalid(format!("{}: TOML parse error: {e}", origin)))?;
+
+        for (key, noun) in SECTIONS {
+            let entries = match doc.remove(key) {
+                None => Vec::new(),
+                Some(Value::Array(entries)) => entries,
+                Some(_) => {
+                    return Err(invalid(format!(
+                        "{}: '{}' must be an array of tables",
+                        origin, key
+                    )));
+                }
+            };
+            let section = self.sections.entry(key).or_default();
+            section.overlay(entries, origin, key, noun)?;
+        }
+        if let Some(key) = doc.keys().next() {
+            return Err(invalid(format!(
+                "{}: unknown top-level key '{}'",
+                origin, key
+            )));
+        }
+
+        self.layers.push(origin.to_string());
+        Ok(())
+    }
+
+    /// Turn the merged tables into definitions; incomplete or unknown fields are reported here.
+    fn finish(mut self) -> Result<ManagerDefs, io::Error> {
+        let managers = self.sections.remove("package_manager").unwrap_or_default();
+        let software = self.sections.remove("software").unwrap_or_default();
+        let mut out = ManagerDefs {
+            disabled: managers.disabled,
+            disabled_software: software.disabled,
+            layers: self.layers,
+            ..ManagerDefs::default()
+        };
+
+        for (id, (table, origins)) in managers.tables {
+            let origin = origins.join(" + ");
+            let def: TomlPackageManager = Value::Table(table).try_into().map_err(|e| {
+                invalid(format!(
+                    "Manager '{}' (from {}) is incomplete or invalid: {}",
+                    id, origin, e
+                ))
+            })?;
+            def.validate()
+                .map_err(|e| invalid(format!("{} (from {})", e, origin)))?;
+            out.defs.insert(id.clone(), def);
+            out.origins.insert(id, origin);
+        }
+
+        for (id, (table, origins)) in software.tables {
+            let origin = origins.join(" + ");
+            let preset: SoftwarePreset = Value::Table(table).try_into().map_err(|e| {
+                invalid(format!(
+                    "Software preset '{}' (from {}) is incomplete or invalid: {}",
+                    id, origin, e
+                ))
+            })?;
+            // A preset installs through a manager, so the manager has to survive the merge
+            let def = out.defs.get(&preset.manager).ok_or_else(|| {
+                invalid(format!(
+                    "Software preset '{}' (from {}) uses unknown manager '{}'",
+                    id, origin, preset.manager
+                ))
+            })?;
+            for pkg in &preset.packages {
+                def.check_package(pkg)
+                    .map_err(|e| invalid(format!("Software preset '{}' (from {}): {}", id, origin, e)))?;
+            }
+            out.software.insert(id.clone(), preset);
+            out.software_origins.insert(id, origin);
+        }
+
+        // `requires` has to name a manager; `after` is only ordering, so a missing one is fine
+        for (id, def) in &out.defs {
+            if let Some(dep) = def.requires.iter().find(|dep| !out.defs.contains_key(*dep)) {
+                return Err(invalid(format!(
+                    "Manager '{}' (from {}) requires unknown manager '{}'",
+                    id, out.origins[id], dep
+                )));
+            }
+        }
+        if let Some(cycle) = out.find_cycle() {
+            return Err(invalid(format!(
+                "Manager ordering has a cycle: {} (check their 'after'/'requires')",
+                cycle.join(" → ")
+            )));
+        }
+
+        Ok(out)
+    }
+}
+
+impl ManagerDefs {
+    /// Managers `id` has to run after: its `requires` and `after`, where defined.
+    pub fn deps(&self, id: &str) -> Vec<&str> {
+        let Some(def) = self.defs.get(id) else { return Vec::new() };
+        def.requires
+            .iter()
+            .chain(&def.after)
+            .map(String::as_str)
+            .filter(|dep| self.defs.contains_key(*dep))
+            .collect()
+    }
+
+    /// A dependency cycle among all definitions, as the ids along it (first id repeated at the end).
+    fn find_cycle(&self) -> Option<Vec<String>> {
+        // Depth-first search; `path` is the current chain, `done` the ids known to be cycle-free
+        fn visit<'a>(
+            defs: &'a ManagerDefs,
+            id: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut BTreeSet<&'a str>,
+        ) -> Option<Vec<String>> {
+            if done.contains(id) {
+                return None;
+            }
+            if let Some(start) = path.iter().position(|p| *p == id) {
+                let mut cycle: Vec<String> = path[start..].iter().map(|p| p.to_string()).collect();
+                cycle.push(id.to_string());
+                return Some(cycle);
+            }
+            path.push(id);
+            for dep in defs.deps(id) {
+                if let Some(cycle) = visit(defs, dep, path, done) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            done.insert(id);
+            None
+        }
+
+        let mut ids: Vec<&String> = self.defs.keys().collect();
+        ids.sort();
+        let mut done = BTreeSet::new();
+        ids.into_iter()
+            .find_map(|id| visit(self, id, &mut Vec::new(), &mut done))
+    }
+
+    /// `ids` in the order a session runs them: every manager after what it requires or runs
+    /// after (when that is in `ids` too), ties broken alphabetically so the order is stable.
+    pub fn run_order<'a>(&self, ids: &[&'a str]) -> Vec<&'a str> {
+        let mut pending: BTreeSet<&'a str> = ids.iter().copied().collect();
+        let mut order = Vec::new();
+        while !pending.is_empty() {
+            let ready = pending
+                .iter()
+                .copied()
+                .find(|id| self.deps(id).iter().all(|dep| !pending.contains(dep)));
+            // Cycles are rejected when loading, but never loop forever over one
+            let next = ready.unwrap_or_else(|| *pending.iter().next().unwrap_or(&""));
+            pending.remove(next);
+            order.push(next);
+        }
+        order
+    }
+}
+
+impl Section {
+    fn overlay(
+        &mut self,
+        entries: Vec<Value>,
+        origin: &str,
+        key: &str,
+        noun: &str,
+    ) -> Result<(), io::Error> {
+        let mut seen: Vec<String> = Vec::new();
+        for (i, entry) in entries.into_iter().enumerate() {
+            let Value::Table(mut entry) = entry else {
+                return Err(invalid(format!(
+                    "{}: {}[{}] is not a table",
+                    origin, key, i
+                )));
+            };
+            let id = match entry.get("id") {
+                Some(Value::String(id)) => id.clone(),
+                _ => {
+                    return Err(invalid(format!(
+                        "{}: {}[{}] has no string 'id'",
+                        origin, key, i
+                    )));
+                }
+            };
+            if seen.contains(&id) {
+                return Err(invalid(format!(
+                    "{}: {} '{}' is defined more than once",
+                    origin, noun, id
+                )));
+            }
+            seen.push(id.clone());
+
+            let disabled = match entry.remove("disabled") {
+                None => false,
+                Some(Value::Boolean(b)) => b,
+                Some(_) => {
+                    return Err(invalid(format!(
+                        "{}: {} '{}': 'disabled' must be a boolean",
+                        origin, noun, id
+                    )));
+                }
+            };
+
+            if disabled {
+                // Disabling and overriding in the same entry is contradictory
+                let extra: Vec<&String> = entry.keys().filter(|k| k.as_str() != "id").collect();
+                if !extra.is_empty() {
+                    let keys = extra
+                        .iter()
+                        .map(|k| k.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    return Err(invalid(format!(
+                        "{}: {} '{}' sets disabled = true together with {}; use one or the other",
+                        origin, noun, id, keys
+                    )));
+                }
+                // Disabling twice (say in /etc and again in the user file) is harmless
+                if self.disabled.contains_key(&id) {
+                    continue;
+                }
+                if self.tables.remove(&id).is_none() {
+                    return Err(invalid(format!(
+                        "{}: cannot disable {} '{}', no earlier layer defines it",
+                        origin, noun, id
+                    )));
+                }
+                self.disabled.insert(id, origin.to_string());
+                continue;
+            }
+
+            match self.tables.get_mut(&id) {
+                Some((table, origins)) => {
+                    let keys = entry
+                        .keys()
+                        .filter(|k| k.as_str() != "id")
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    merge_table(table, entry);
+                    origins.push(format!("{} ({})", origin, keys.join(", ")));
+                }
+                None => {
+                    self.disabled.remove(&id);
+                    self.tables.insert(id, (entry, vec![origin.to_string()]));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Overlay `over` onto `base`: nested tables merge key by key, anything else is replaced.
+fn merge_table(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(b)), Value::Table(o)) => merge_table(b, o),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAKE: &str = "[[package_manager]]\nid = \"fake\"\ncheck = \"true\"\nbootstrap = \"\"\nenable = \"\"\ninstall = \"fake {{package}}\"\n";
+    const DISABLE: &str = "[[package_manager]]\nid = \"fake\"\ndisabled = true\n";
+
+    #[test]
+    fn disabling_twice_is_not_an_error() {
+        let defs = from_layers(&[FAKE, DISABLE, DISABLE]).unwrap();
+        assert!(!defs.defs.contains_key("fake"));
+        assert_eq!(defs.disabled["fake"], "layer 1");
+    }
+
+    #[test]
+    fn disabling_an_unknown_manager_is_an_error() {
+        let err = from_layers(&[DISABLE]).err().unwrap();
+        assert!(err.to_string().contains("no earlier layer defines it"));
+    }
+
+    /// A complete manager definition named `id`, with extra keys such as `requires = [...]`.
+    fn def(id: &str, extra: &str) -> String {
+        format!(
+            "[[package_manager]]\nid = \"{}\"\ncheck = \"true\"\nbootstrap = \"\"\nenable = \"\"\ninstall = \"{} {{{{package}}}}\"\n{}\n",
+            id, id, extra
+        )
+    }
+
+    #[test]
+    fn run_order_follows_requires_and_after() {
+        let layer = [
+            def("zeta", ""),
+            def("app", "requires = [\"zeta\"]"),
+            def("mid", "after = [\"app\"]"),
+        ]
+        .concat();
+        let defs = from_layers(&[&layer]).unwrap();
+        assert_eq!(defs.run_order(&["mid", "app", "zeta"]), ["zeta", "app", "mid"]);
+        // `after` only orders among the managers that run
+        assert_eq!(defs.run_order(&["mid", "zeta"]), ["mid", "zeta"]);
+    }
+
+    #[test]
+    fn run_order_breaks_ties_alphabetically() {
+        let layer = [def("c", ""), def("a", ""), def("b", ""), def("d", "requires = [\"c\"]")].concat();
+        let defs = from_layers(&[&layer]).unwrap();
+        assert_eq!(defs.run_order(&["d", "c", "b", "a"]), ["a", "b", "c", "d"]);
+        assert_eq!(defs.run_order(&["d", "b", "c"]), ["b", "c", "d"]);
+    }
+
+    #[test]
+    fn after_an_undefined_manager_is_ignored() {
+        let layer = [def("a", "after = [\"ghost\"]"), def("b", "")].concat();
+        let defs = from_layers(&[&layer]).unwrap();
+        assert!(defs.deps("a").is_empty());
+        assert_eq!(defs.run_order(&["b", "a"]), ["a", "b"]);
+
+        // `requires` on the other hand has to exist
+        let err = from_layers(&[&def("a", "requires = [\"ghost\"]")]).err().unwrap();
+        assert!(err.to_string().contains("requires unknown manager 'ghost'"), "{}", err);
+    }
+
+    #[test]
+    fn dependency_cycles_are_rejected_with_their_path() {
+        let layer = [def("a", "requires = [\"b\"]"), def("b", "after = [\"a\"]")].concat();
+        let err = from_layers(&[&layer]).err().unwrap();
+        assert!(err.to_string().contains("has a cycle: a → b → a"), "{}", err);
+
+        // A later layer can close the cycle too
+        let err = from_layers(&[&def("a", ""), &def("b", "after = [\"a\"]"), "[[package_manager]]\nid = \"a\"\nafter = [\"b\"]\n"])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("a → b → a"), "{}", err);
+    }
+}
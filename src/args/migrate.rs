@@ -0,0 +1,157 @@
+// migrate.rs
+
+use serde_json::{Map, Value};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::args::formats::{self, Format};
+
+/// Config version written by this build. Documents without a `version` key are version 0.
+pub const CONFIG_VERSION: u64 = 1;
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[n]` upgrades a document from version `n` to `n + 1`, in memory.
+/// Add a step here (and bump CONFIG_VERSION) whenever the `Config` layout changes.
+const MIGRATIONS: [(&str, Migration); CONFIG_VERSION as usize] = [
+    // 0 -> 1: the layout is unchanged, version 1 only adds the marker
+    ("add the version marker", |_| {}),
+];
+
+/// The document's `version`, 0 when it has none.
+pub fn version_of(doc: &Value) -> Result<u64, String> {
+    match doc.get("version") {
+        None => Ok(0),
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| format!("'version' must be a non-negative integer, found {}", v)),
+    }
+}
+
+/// Upgrade `doc` in place to CONFIG_VERSION and return the version it started at.
+/// A version newer than this build is an error rather than a guess.
+pub fn upgrade(doc: &mut Value) -> Result<u64, String> {
+    let from = version_of(doc)?;
+    if from > CONFIG_VERSION {
+        return Err(format!(
+            "config version {} is newer than this horns supports ({}); update horns",
+            from, CONFIG_VERSION
+        ));
+    }
+    let Value::Object(map) = doc else {
+        return Err("expected an object at the top level".to_string());
+    };
+    for (step, migrate) in &MIGRATIONS[from as usize..] {
+        if super::verbosity::verbose() {
+            println!("(migrate) {}", step);
+        }
+        migrate(map);
+    }
+    // Keep the marker as the first key so it's the first thing a reader sees
+    map.shift_remove("version");
+    map.shift_insert(0, "version".to_string(), Value::from(CONFIG_VERSION));
+    Ok(from)
+}
+
+/// Rewrite `path` at CONFIG_VERSION in its own format, keeping the original as `<file>.bak`
+/// (`<file>.bak.1`, `.bak.2`, ... when that's taken; an old backup is never overwritten).
+/// Returns the version it started at and the backup, or None when it was already current.
+/// Comments don't survive the rewrite; the backup keeps them.
+pub fn migrate_file(path: &Path) -> Result<Option<(u64, PathBuf)>, io::Error> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let format = Format::from_path(path).ok_or_else(|| {
+        invalid(format!(
+            "{}: unsupported extension; expected one of .{}",
+            path.display(),
+            formats::EXTENSIONS.join(", .")
+        ))
+    })?;
+    let src = fs::read_to_string(path)?;
+    let (mut value, positions) =
+        formats::parse(format, &src).map_err(|d| invalid(format!("{}: {}", path.display(), d)))?;
+
+    let from = upgrade(&mut value).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+    if from == CONFIG_VERSION {
+        return Ok(None);
+    }
+    // Only write something horns can read back
+    formats::to_config(&value, &positions)
+        .map_err(|d| invalid(format!("{}: {}", path.display(), d)))?;
+    let text = formats::render(format, &value).map_err(invalid)?;
+
+    let backup = keep_original(path, &src)?;
+    fs::write(path, text.trim_end().to_string() + "\n")?;
+    Ok(Some((from, backup)))
+}
+
+/// Write `src` to the first of `<path>.bak`, `<path>.bak.1`, ... that doesn't exist yet.
+fn keep_original(path: &Path, src: &str) -> Result<PathBuf, io::Error> {
+    for n in 0u32.. {
+        let mut name = path.as_os_str().to_owned();
+        name.push(if n == 0 { ".bak".to_string() } else { format!(".bak.{}", n) });
+        let backup = PathBuf::from(name);
+        // create_new, so a backup that appears in the meantime isn't clobbered either
+        match OpenOptions::new().write(true).create_new(true).open(&backup) {
+            Ok(mut file) => {
+                file.write_all(src.as_bytes())?;
+                return Ok(backup);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("ran out of backup names")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn upgrade_adds_the_marker_to_version_0() {
+        let mut doc = json!({ "package_management": {}, "custom_packages": {} });
+        assert_eq!(upgrade(&mut doc), Ok(0));
+        assert_eq!(doc["version"], json!(CONFIG_VERSION));
+        // The marker goes first
+        assert_eq!(doc.as_object().unwrap().keys().next().unwrap(), "version");
+    }
+
+    #[test]
+    fn upgrade_leaves_a_current_document_alone() {
+        let mut doc = json!({ "version": CONFIG_VERSION, "users": [] });
+        let before = doc.clone();
+        assert_eq!(upgrade(&mut doc), Ok(CONFIG_VERSION));
+        assert_eq!(doc, before);
+    }
+
+    #[test]
+    fn upgrade_refuses_a_newer_version() {
+        let mut doc = json!({ "version": CONFIG_VERSION + 1 });
+        let err = upgrade(&mut doc).unwrap_err();
+        assert!(err.contains("newer than this horns supports"), "{}", err);
+        assert_eq!(doc["version"], json!(CONFIG_VERSION + 1));
+    }
+
+    #[test]
+    fn migrate_never_overwrites_a_backup() {
+        let dir = std::env::temp_dir().join(format!("horns-migrate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("horns.json");
+        let old = r#"{ "package_management": { "pre": [], "post": [] }, "custom_packages": { "pre": [], "post": [] } }"#;
+        fs::write(&path, old).unwrap();
+        fs::write(dir.join("horns.json.bak"), "keep me").unwrap();
+
+        let (from, backup) = migrate_file(&path).unwrap().unwrap();
+        assert_eq!(from, 0);
+        assert_eq!(backup, dir.join("horns.json.bak.1"));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), old);
+        assert_eq!(fs::read_to_string(dir.join("horns.json.bak")).unwrap(), "keep me");
+        assert_eq!(migrate_file(&path).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
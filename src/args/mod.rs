@@ -1,3 +1,11 @@
 pub mod json_to_array;
 pub mod terminal;
-pub mod json_and_config;
\ No newline at end of file
+pub mod json_and_config;
+pub mod verbosity;
+pub mod diagnostics;
+pub mod managers;
+pub mod nuon;
+pub mod import;
+pub mod formats;
+pub mod migrate;
+pub mod schema;
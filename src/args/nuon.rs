@@ -0,0 +1,212 @@
+// nuon.rs
+
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+
+use crate::args::diagnostics::{pointer, Diagnostic, Positions};
+
+/// Parse NUON (Nushell object notation) into a JSON value, plus the line/column of every
+/// pointer in it (same shape as `locate_json`: record fields point at their key).
+///
+/// Covers what deploy.nuon files use: records, lists, quoted/raw/backtick strings, bare
+/// words, numbers, booleans, null and `#` comments. Bare words that aren't a number or a
+/// keyword become strings, so `home: /usr/ellie` reads as `"/usr/ellie"`.
+pub fn parse(src: &str) -> Result<(Value, Positions), Diagnostic> {
+    let mut parser = Parser {
+        chars: src.chars().collect(),
+        pos: 0,
+        line: 1,
+        col: 1,
+        out: HashMap::new(),
+    };
+    parser.skip_ws();
+    parser.out.insert(String::new(), (parser.line, parser.col));
+    let value = parser.value(String::new())?;
+    parser.skip_ws();
+    if parser.peek().is_some() {
+        return Err(parser.error("unexpected trailing input"));
+    }
+    Ok((value, parser.out))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    out: Positions,
+}
+
+/// Characters that end a bare word.
+fn ends_word(c: char) -> bool {
+    c.is_whitespace() || ",:[]{}#;".contains(c)
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> Diagnostic {
+        Diagnostic {
+            pointer: String::new(),
+            line: Some(self.line),
+            column: Some(self.col),
+            message: format!("NUON parse error: {}", message),
+        }
+    }
+
+    /// Skip whitespace, commas and comments; NUON separates items with any of them.
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ',' {
+                self.bump();
+            } else if c == '#' {
+                while !matches!(self.peek(), None | Some('\n')) {
+                    self.bump();
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn value(&mut self, path: String) -> Result<Value, Diagnostic> {
+        self.skip_ws();
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some('{') => self.record(path),
+            Some('[') => self.list(path),
+            Some('"' | '\'' | '`') => self.quoted().map(Value::String),
+            Some(c) if ends_word(c) => Err(self.error(&format!("unexpected '{}'", c))),
+            Some(_) => Ok(bare_value(self.word())),
+        }
+    }
+
+    fn record(&mut self, path: String) -> Result<Value, Diagnostic> {
+        self.bump();
+        let mut map = Map::new();
+        loop {
+            self.skip_ws();
+            let at = (self.line, self.col);
+            let key = match self.peek() {
+                None => return Err(self.error("unclosed record, expected '}'")),
+                Some('}') => {
+                    self.bump();
+                    return Ok(Value::Object(map));
+                }
+                Some('"' | '\'' | '`') => self.quoted()?,
+                Some(c) if ends_word(c) => {
+                    return Err(self.error(&format!("expected a record key, found '{}'", c)));
+                }
+                Some(_) => self.word(),
+            };
+            self.skip_ws();
+            if self.peek() != Some(':') {
+                return Err(self.error(&format!("expected ':' after key '{}'", key)));
+            }
+            self.bump();
+            let child = format!("{}{}", path, pointer(&[&key]));
+            self.out.insert(child.clone(), at);
+            let value = self.value(child)?;
+            map.insert(key, value);
+        }
+    }
+
+    fn list(&mut self, path: String) -> Result<Value, Diagnostic> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                None => return Err(self.error("unclosed list, expected ']'")),
+                Some(']') => {
+                    self.bump();
+                    return Ok(Value::Array(items));
+                }
+                Some(';') => return Err(self.error("table literals ([[..]; [..]]) are not supported")),
+                Some(_) => {}
+            }
+            let child = format!("{}/{}", path, items.len());
+            self.out.insert(child.clone(), (self.line, self.col));
+            items.push(self.value(child)?);
+        }
+    }
+
+    /// `"..."` with escapes, `'...'` and `` `...` `` taken literally.
+    fn quoted(&mut self) -> Result<String, Diagnostic> {
+        let quote = self.bump().unwrap_or('"');
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("unterminated string")),
+                Some(c) if c == quote => return Ok(s),
+                Some('\\') if quote == '"' => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('0') => s.push('\0'),
+                    Some('u') => {
+                        // \u{1F600}
+                        if self.bump() != Some('{') {
+                            return Err(self.error("expected '{' after \\u"));
+                        }
+                        let mut hex = String::new();
+                        while let Some(c) = self.bump() {
+                            if c == '}' {
+                                break;
+                            }
+                            hex.push(c);
+                        }
+                        let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                        s.push(c.ok_or_else(|| self.error("invalid unicode escape"))?);
+                    }
+                    Some(other) => s.push(other),
+                    None => return Err(self.error("unterminated string")),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if ends_word(c) {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+        s
+    }
+}
+
+/// Keywords and numbers keep their type; anything else is a bare string.
+fn bare_value(word: String) -> Value {
+    match word.as_str() {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        "null" => return Value::Null,
+        _ => {}
+    }
+    if let Ok(n) = word.parse::<i64>() {
+        return Value::Number(n.into());
+    }
+    if let Some(n) = word.parse::<f64>().ok().and_then(Number::from_f64) {
+        return Value::Number(n);
+    }
+    Value::String(word)
+}
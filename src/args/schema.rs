@@ -0,0 +1,133 @@
+// schema.rs
+
+use serde_json::{json, Map, Value};
+
+use crate::args::diagnostics::{Diagnostic, Positions};
+use crate::args::json_and_config::{FlagSpec, FlagType, ACCOUNT_NAME};
+use crate::args::json_to_array::Config;
+use crate::args::managers::ManagerDefs;
+use crate::args::migrate::CONFIG_VERSION;
+
+/// JSON Schema for the config: the `Config` structs, with the dynamic manager, package and
+/// software keys filled in from the merged definitions so editors can complete them.
+pub fn config_schema(defs: &ManagerDefs) -> Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(Config)).unwrap_or_default();
+    schema["title"] = json!("horns config");
+    schema["properties"]["version"] = json!({
+        "description": "Config layout version; older documents are migrated automatically",
+        "const": CONFIG_VERSION,
+    });
+
+    let mut ids: Vec<&String> = defs.defs.keys().collect();
+    ids.sort();
+
+    // package_management: one key per manager, flags limited to the manager's `others`
+    let pm = &mut schema["$defs"]["PackageManagement"];
+    for id in &ids {
+        let def = &defs.defs[*id];
+        let mut flags = Map::new();
+        flags.insert("enabled".to_string(), json!({ "type": "boolean" }));
+        let mut required = Vec::new();
+        let mut others: Vec<(&String, &FlagSpec)> = def.others.iter().flatten().collect();
+        others.sort_by_key(|(name, _)| *name);
+        for (name, flag) in others {
+            flags.insert(name.clone(), flag_schema(flag));
+            if flag.required() {
+                required.push(name.clone());
+            }
+        }
+        let mut object = json!({
+            "type": "object",
+            "properties": flags,
+            "required": ["enabled"],
+            "additionalProperties": false,
+        });
+        // Required flags only matter when the manager runs, so the short `true` form can't carry them
+        let short = if required.is_empty() {
+            json!({ "type": "boolean" })
+        } else {
+            object["if"] = json!({ "properties": { "enabled": { "const": true } } });
+            object["then"] = json!({ "required": required });
+            json!({ "const": false })
+        };
+        pm["properties"][id.as_str()] = json!({
+            "description": format!("Enable '{}' (install: {})", id, def.install),
+            "anyOf": [short, object],
+        });
+    }
+    pm["additionalProperties"] = json!(false);
+
+    // custom_packages: one list per manager, names checked against its package_pattern
+    let cp = &mut schema["$defs"]["CustomPackages"];
+    for id in &ids {
+        let def = &defs.defs[*id];
+        cp["properties"][id.as_str()] = json!({
+            "description": format!("Packages installed with '{}'", id),
+            "type": "array",
+            "items": { "type": "string", "pattern": def.anchored_pattern() },
+        });
+    }
+    cp["additionalProperties"] = json!(false);
+
+    // software: one boolean per preset
+    let mut presets = Map::new();
+    let mut preset_ids: Vec<&String> = defs.software.keys().collect();
+    preset_ids.sort();
+    for id in preset_ids {
+        let preset = &defs.software[id];
+        presets.insert(
+            id.clone(),
+            json!({
+                "description": format!("{} via {}", preset.packages.join(", "), preset.manager),
+                "type": "boolean",
+            }),
+        );
+    }
+    schema["properties"]["software"] = json!({
+        "type": "object",
+        "properties": presets,
+        "additionalProperties": false,
+    });
+
+    let user = &mut schema["$defs"]["UserSpec"]["properties"];
+    user["name"]["pattern"] = json!(ACCOUNT_NAME);
+    user["groups"]["items"]["pattern"] = json!(ACCOUNT_NAME);
+
+    schema
+}
+
+fn flag_schema(flag: &FlagSpec) -> Value {
+    let FlagSpec::Typed(t) = flag else {
+        return json!({
+            "description": flag.description(),
+            "type": ["string", "boolean", "number", "null"],
+        });
+    };
+    let mut schema = match t.kind {
+        FlagType::Bool => json!({ "type": "boolean" }),
+        FlagType::String => json!({ "type": "string" }),
+        FlagType::Int => json!({ "type": "integer" }),
+        FlagType::Enum => json!({ "enum": t.values }),
+    };
+    schema["description"] = json!(t.description);
+    if let Some(default) = &t.default {
+        schema["default"] = default.clone();
+    }
+    schema
+}
+
+/// Validate a parsed config against `schema`, one located diagnostic per violation.
+/// Err when the schema itself can't be compiled (e.g. a package_pattern only Rust accepts).
+pub fn check(value: &Value, schema: &Value, positions: &Positions) -> Result<Vec<Diagnostic>, String> {
+    let validator = jsonschema::validator_for(schema).map_err(|e| e.to_string())?;
+    Ok(validator
+        .iter_errors(value)
+        .map(|e| {
+            Diagnostic::at(
+                positions,
+                e.instance_path.as_str().to_string(),
+                format!("Schema error: {}", e),
+            )
+        })
+        .collect())
+}
@@ -1,5 +1,11 @@
+#[cfg(test)]
+use std::collections::HashMap;
 use std::io;
 use std::process::Command;
+#[cfg(test)]
+use std::sync::Mutex;
+#[cfg(test)]
+use std::time::Duration;
 
 pub fn output(command: &str) -> Result<(), io::Error> {
     let status = Command::new("bash").arg("-c").arg(command).status()?;
@@ -7,10 +13,10 @@ pub fn output(command: &str) -> Result<(), io::Error> {
     if status.success() {
         Ok(())
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Command exited with code {:?}", status.code()),
-        ))
+        Err(io::Error::other(format!(
+            "Command exited with code {:?}",
+            status.code()
+        )))
     }
 }
 
@@ -20,9 +26,97 @@ pub fn output_access(cmd: &str) -> Result<String, io::Error> {
     if res.status.success() {
         Ok(String::from_utf8_lossy(&res.stdout).to_string())
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
+        Err(io::Error::other(
             String::from_utf8_lossy(&res.stderr).to_string(),
         ))
     }
 }
+
+/// Runs shell command strings for the session and backup.
+/// Same contract as `output_access`: Ok(stdout) on exit 0, Err(stderr) otherwise.
+pub trait Executor: Sync {
+    fn run(&self, cmd: &str) -> Result<String, io::Error>;
+
+    /// Run with the terminal attached (e.g. `passwd` prompts); nothing is captured.
+    fn run_interactive(&self, cmd: &str) -> Result<(), io::Error>;
+}
+
+/// The real executor: every command goes through `bash -c`.
+pub struct Bash;
+
+impl Executor for Bash {
+    fn run(&self, cmd: &str) -> Result<String, io::Error> {
+        output_access(cmd)
+    }
+
+    fn run_interactive(&self, cmd: &str) -> Result<(), io::Error> {
+        output(cmd)
+    }
+}
+
+/// Records commands instead of running them, so tests can assert on the exact sequence
+/// without root or real package managers. Unknown commands succeed with empty stdout.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Recording {
+    calls: Mutex<Vec<String>>,
+    outputs: HashMap<String, String>,
+    failures: HashMap<String, String>,
+    panics: Vec<String>,
+    delay: Duration,
+}
+
+#[cfg(test)]
+impl Recording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `cmd` succeed with the given stdout.
+    pub fn output(mut self, cmd: &str, stdout: &str) -> Self {
+        self.outputs.insert(cmd.to_string(), stdout.to_string());
+        self
+    }
+
+    /// Make `cmd` fail with the given stderr.
+    pub fn fail(mut self, cmd: &str, stderr: &str) -> Self {
+        self.failures.insert(cmd.to_string(), stderr.to_string());
+        self
+    }
+
+    /// Make `cmd` panic, like a bug in the code running it would.
+    pub fn panic_on(mut self, cmd: &str) -> Self {
+        self.panics.push(cmd.to_string());
+        self
+    }
+
+    /// Make every command take `delay`, so parallel runs actually overlap.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Every command run so far, in order.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Executor for Recording {
+    fn run(&self, cmd: &str) -> Result<String, io::Error> {
+        self.calls.lock().unwrap().push(cmd.to_string());
+        std::thread::sleep(self.delay);
+        if self.panics.iter().any(|p| p == cmd) {
+            panic!("Recording: panic on '{}'", cmd);
+        }
+        if let Some(stderr) = self.failures.get(cmd) {
+            return Err(io::Error::other(stderr.clone()));
+        }
+        Ok(self.outputs.get(cmd).cloned().unwrap_or_default())
+    }
+
+    fn run_interactive(&self, cmd: &str) -> Result<(), io::Error> {
+        self.run(cmd).map(|_| ())
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const QUIET: u8 = 0;
+const NORMAL: u8 = 1;
+const VERBOSE: u8 = 2;
+
+static LEVEL: AtomicU8 = AtomicU8::new(NORMAL);
+
+/// Set once from the global `--verbose` / `--quiet` options.
+pub fn set(verbose: bool, quiet: bool) {
+    let level = if quiet {
+        QUIET
+    } else if verbose {
+        VERBOSE
+    } else {
+        NORMAL
+    };
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Debug output: parsed configs, internal steps.
+pub fn verbose() -> bool {
+    LEVEL.load(Ordering::Relaxed) >= VERBOSE
+}
+
+/// Suppress command output and reports; errors are always printed.
+pub fn quiet() -> bool {
+    LEVEL.load(Ordering::Relaxed) == QUIET
+}
@@ -0,0 +1,32 @@
+// src/backup/backup_file.rs
+
+use crate::args::json_and_config::TomlPackageManager;
+use crate::args::terminal;
+use crate::backup::{capture, store};
+use crate::restore::restore_file::generate_directory;
+use std::{collections::HashMap, fs, path::PathBuf};
+
+/// File name the backup is written under inside its timestamped directory.
+pub const CONFIG_FILE: &str = "horns.json";
+
+/// Capture the installed packages and write them to `configs/<timestamp>-<name>/horns.json`.
+pub fn local(
+    name: &str,
+    toml_defs: &HashMap<String, TomlPackageManager>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let config = capture::capture(toml_defs, &terminal::Bash);
+
+    let sanitized = match name.trim_matches('/').replace('/', "_") {
+        s if s.is_empty() || s == "." => "backup".to_string(),
+        s => s,
+    };
+    let dir = generate_directory(&sanitized)?;
+    let file = dir.join(CONFIG_FILE);
+    fs::write(&file, serde_json::to_string_pretty(&config)?)?;
+    println!("Backup written to: {}", file.display());
+
+    let entry = store::register(&dir, &sanitized, "backup")?;
+    println!("Restore it later with: restore {}", entry.id);
+
+    Ok(file)
+}
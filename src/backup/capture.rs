@@ -0,0 +1,199 @@
+// src/backup/capture.rs
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::args::json_and_config::{TomlPackageManager, ACCOUNT_NAME};
+use crate::args::json_to_array::{
+    Config, CustomPackages, HomeSpec, ManagerSpec, PackageManagement, UserSpec,
+};
+use crate::args::migrate;
+use crate::args::terminal::Executor;
+
+/// Inspect the machine through every manager in Packages.toml and build a Config
+/// that `restore` can replay.
+pub fn capture(toml_defs: &HashMap<String, TomlPackageManager>, exec: &dyn Executor) -> Config {
+    let mut managers: HashMap<String, ManagerSpec> = HashMap::new();
+    let mut by_manager: HashMap<String, Vec<String>> = HashMap::new();
+
+    for (id, def) in toml_defs {
+        // A manager that isn't installed is still recorded, just disabled
+        let present = def.check.trim().is_empty() || exec.run(&def.check).is_ok();
+        if !present {
+            println!("(skip) '{}' not installed", id);
+            managers.insert(id.clone(), ManagerSpec::Bool(false));
+            continue;
+        }
+        managers.insert(id.clone(), ManagerSpec::Bool(true));
+
+        let Some(list) = &def.list else {
+            println!(
+                "(info) '{}' has no list command; recording manager only",
+                id
+            );
+            continue;
+        };
+
+        let listed = exec
+            .run(&list.command)
+            .map_err(|e| e.to_string())
+            .and_then(|out| list.parse(&out));
+        match listed {
+            Ok(mut pkgs) => {
+                // restore would reject the whole config over one name it can't install safely
+                pkgs.retain(|pkg| match def.check_package(pkg) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        eprintln!("(warn) {}; not recorded", e);
+                        false
+                    }
+                });
+                println!("(info) '{}': {} package(s)", id, pkgs.len());
+                by_manager.insert(id.clone(), pkgs);
+            }
+            Err(e) => {
+                eprintln!("(warn) list for '{}' failed: {}", id, e.trim_end());
+            }
+        }
+    }
+
+    let users = capture_users(exec);
+
+    Config {
+        version: migrate::CONFIG_VERSION,
+        package_management: PackageManagement {
+            pre: Vec::new(),
+            post: Vec::new(),
+            managers,
+        },
+        custom_packages: CustomPackages {
+            pre: Vec::new(),
+            post: Vec::new(),
+            by_manager,
+        },
+        users,
+        // Presets are a restore-side shorthand; a backup records the packages themselves
+        software: HashMap::new(),
+    }
+}
+
+/// Regular login accounts: UID_MIN..=UID_MAX from login.defs defaults, minus `nobody`.
+const UID_RANGE: std::ops::RangeInclusive<u32> = 1000..=60000;
+
+/// Non-system users from `getent passwd`, with their groups and comment.
+/// Passwords can't be carried over, so `password` stays off.
+fn capture_users(exec: &dyn Executor) -> Vec<UserSpec> {
+    let passwd = match exec.run("getent passwd") {
+        Ok(out) => out,
+        Err(e) => {
+            eprintln!("(warn) could not list users: {}", e.to_string().trim_end());
+            return Vec::new();
+        }
+    };
+
+    let account_name = Regex::new(ACCOUNT_NAME).expect("account name regex");
+    let mut users = Vec::new();
+    for line in passwd.lines() {
+        // name:x:uid:gid:gecos:home:shell
+        let fields: Vec<&str> = line.split(':').collect();
+        let [name, _, uid, _, gecos, home, ..] = fields.as_slice() else {
+            continue;
+        };
+        let Ok(uid) = uid.parse::<u32>() else {
+            continue;
+        };
+        if !UID_RANGE.contains(&uid) {
+            continue;
+        }
+        if !account_name.is_match(name) {
+            eprintln!("(warn) user '{}' is not a name useradd accepts; not recorded", name);
+            continue;
+        }
+
+        // `id -nG` lists the primary group first; only supplementary groups are restored
+        let mut groups: Vec<String> = exec
+            .run(&format!("id -nG {}", name))
+            .map(|out| out.split_whitespace().skip(1).map(str::to_string).collect())
+            .unwrap_or_default();
+        let root = groups.iter().any(|g| g == "sudo");
+        groups.retain(|g| g != "sudo");
+        groups.retain(|g| {
+            let valid = account_name.is_match(g);
+            if !valid {
+                eprintln!("(warn) group '{}' of user '{}' is not a name groupadd accepts; not recorded", g, name);
+            }
+            valid
+        });
+
+        let comment = gecos.split(',').next().unwrap_or("").trim();
+        // restore only takes absolute homes; anything else falls back to /home/<name>
+        let home = if *home == format!("/home/{}", name) || !home.starts_with('/') {
+            HomeSpec::Create(true)
+        } else {
+            HomeSpec::Path(home.to_string())
+        };
+
+        users.push(UserSpec {
+            name: name.to_string(),
+            home,
+            root,
+            password: false,
+            groups,
+            comment: (!comment.is_empty()).then(|| comment.to_string()),
+        });
+    }
+    println!("(info) users: {} account(s)", users.len());
+    users
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::json_and_config::compare_and_report;
+    use crate::args::managers;
+    use crate::args::terminal::Recording;
+    use std::fs;
+
+    const DEFS: &str = r#"
+[[package_manager]]
+id = "fake"
+check = "command -v fake"
+bootstrap = "install-fake"
+enable = "enable-fake"
+install = "fake add {{package}}"
+list = { command = "fake list" }
+"#;
+
+    #[test]
+    fn a_capture_passes_restore_validation() {
+        let defs = managers::from_layers(&[DEFS]).unwrap();
+        let exec = Recording::new()
+            .output("fake list", "curl\nfoo; rm -rf ~\n$(id)\nvim\n")
+            .output(
+                "getent passwd",
+                "root:x:0:0:root:/root:/bin/bash\n\
+                 alice:x:1000:1000:Alice A,,,:/home/alice:/bin/bash\n\
+                 Bad.User:x:1001:1001::/home/Bad.User:/bin/sh\n\
+                 bob:x:1002:1002::/srv/bob:/bin/sh\n",
+            )
+            .output("id -nG alice", "alice sudo docker Bad!Group\n")
+            .output("id -nG bob", "bob\n");
+        let config = capture(&defs.defs, &exec);
+
+        assert_eq!(config.custom_packages.by_manager["fake"], ["curl", "vim"]);
+        let names: Vec<&str> = config.users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["alice", "bob"]);
+        assert!(config.users[0].root);
+        assert_eq!(config.users[0].groups, ["docker"]);
+        assert!(!exec.calls().iter().any(|c| c.contains("Bad.User")));
+
+        let path = std::env::temp_dir().join(format!("horns-capture-{}.json", std::process::id()));
+        fs::write(&path, serde_json::to_string_pretty(&config).unwrap()).unwrap();
+        let checked = compare_and_report(&path, &defs);
+        fs::remove_file(&path).unwrap();
+        if let Err(diags) = checked {
+            panic!("{:?}", diags);
+        }
+    }
+}
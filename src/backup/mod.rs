@@ -0,0 +1,3 @@
+pub mod backup_file;
+pub mod capture;
+pub mod store;
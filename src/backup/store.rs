@@ -0,0 +1,271 @@
+// src/backup/store.rs
+
+use crate::args::verbosity;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// One indexed config directory.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StoreEntry {
+    pub id: String,
+    pub name: String,
+    pub created: String,
+    pub path: PathBuf,
+    pub source: String, // "backup" or the remote URL it was cloned from
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct StoreIndex {
+    pub backups: Vec<StoreEntry>,
+}
+
+/// `$XDG_DATA_HOME/horns`, falling back to `~/.local/share/horns`.
+pub fn store_dir() -> Result<PathBuf, io::Error> {
+    if let Some(xdg) = env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(xdg).join("horns"));
+    }
+    let home = env::var_os("HOME")
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "neither XDG_DATA_HOME nor HOME is set",
+            )
+        })?;
+    Ok(PathBuf::from(home).join(".local/share/horns"))
+}
+
+/// Where `generate_directory` puts the `<timestamp>-<name>` directories.
+pub fn configs_root() -> Result<PathBuf, io::Error> {
+    Ok(store_dir()?.join("configs"))
+}
+
+fn index_path() -> Result<PathBuf, io::Error> {
+    Ok(store_dir()?.join("index.json"))
+}
+
+pub fn load() -> Result<StoreIndex, io::Error> {
+    let path = index_path()?;
+    if !path.exists() {
+        return Ok(StoreIndex::default());
+    }
+    let json = fs::read_to_string(&path)?;
+    serde_json::from_str(&json).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Corrupt backup index {}: {}", path.display(), e),
+        )
+    })
+}
+
+fn save(index: &StoreIndex) -> Result<(), io::Error> {
+    let path = index_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string_pretty(index).map_err(io::Error::other)?;
+    fs::write(path, json)
+}
+
+/// FNV-1a over the directory path: the ID only depends on where the config lives,
+/// so re-registering the same directory yields the same ID.
+fn short_id(path: &Path, len: usize) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in path.as_os_str().as_encoded_bytes() {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)[..len].to_string()
+}
+
+/// Add a config directory to the index (or return its existing entry).
+pub fn register(dir: &Path, name: &str, source: &str) -> Result<StoreEntry, io::Error> {
+    let mut index = load()?;
+    if let Some(existing) = index.backups.iter().find(|e| e.path == dir) {
+        return Ok(existing.clone());
+    }
+
+    let entry = StoreEntry {
+        id: unique_id(&index, dir),
+        name: name.to_string(),
+        created: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        path: dir.to_path_buf(),
+        source: source.to_string(),
+    };
+    index.backups.push(entry.clone());
+    save(&index)?;
+    if !verbosity::quiet() {
+        println!("Registered backup {} ({})", entry.id, entry.path.display());
+    }
+    Ok(entry)
+}
+
+/// A new ID for `dir`: its hash, grown from 8 hex digits until no other entry has it, and
+/// with a `-2`, `-3`, ... counter in the (unlikely) case that all 16 digits collide.
+fn unique_id(index: &StoreIndex, dir: &Path) -> String {
+    let taken = |id: &str| index.backups.iter().any(|e| e.id == id);
+    let mut len = 8;
+    let mut id = short_id(dir, len);
+    while len < 16 && taken(&id) {
+        len += 1;
+        id = short_id(dir, len);
+    }
+    let full = id.clone();
+    let mut n = 1;
+    while taken(&id) {
+        n += 1;
+        id = format!("{}-{}", full, n);
+    }
+    id
+}
+
+/// Resolve a full ID or a unique prefix of one.
+pub fn resolve(id: &str) -> Result<StoreEntry, io::Error> {
+    find(&load()?, id)
+}
+
+fn find(index: &StoreIndex, id: &str) -> Result<StoreEntry, io::Error> {
+    // Every ID starts with "", so an empty prefix would pick the only backup there is
+    if id.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Backup ID is empty (see `backups list`)",
+        ));
+    }
+    let matches: Vec<&StoreEntry> = index
+        .backups
+        .iter()
+        .filter(|e| e.id.starts_with(id))
+        .collect();
+    match matches.as_slice() {
+        [] => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No backup with ID '{}' (see `backups list`)", id),
+        )),
+        [single] => Ok((*single).clone()),
+        many => {
+            // An exact match wins over longer IDs sharing the prefix
+            if let Some(exact) = many.iter().find(|e| e.id == id) {
+                return Ok((*exact).clone());
+            }
+            let ids = many
+                .iter()
+                .map(|e| e.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Backup ID '{}' is ambiguous: {}", id, ids),
+            ))
+        }
+    }
+}
+
+/// Drop an entry from the index. Its directory is deleted only when it lives inside the store.
+pub fn remove(id: &str) -> Result<StoreEntry, io::Error> {
+    let entry = resolve(id)?;
+    let mut index = load()?;
+    index.backups.retain(|e| e.id != entry.id);
+    save(&index)?;
+
+    if entry.path.starts_with(configs_root()?) && entry.path.is_dir() {
+        fs::remove_dir_all(&entry.path)?;
+        println!("Deleted {}", entry.path.display());
+    } else {
+        println!(
+            "Left {} in place (outside the backup store)",
+            entry.path.display()
+        );
+    }
+    Ok(entry)
+}
+
+pub fn print_list() -> Result<(), io::Error> {
+    let index = load()?;
+    if index.backups.is_empty() {
+        println!("No backups yet (run `backup <name>` or `restore --remote <url>`)");
+        return Ok(());
+    }
+
+    println!("{:<10} {:<20} {:<24} SOURCE", "ID", "CREATED", "NAME");
+    for e in &index.backups {
+        let missing = if e.path.is_dir() { "" } else { " (missing)" };
+        println!(
+            "{:<10} {:<20} {:<24} {}{}",
+            e.id, e.created, e.name, e.source, missing
+        );
+    }
+    Ok(())
+}
+
+pub fn print_show(id: &str) -> Result<(), io::Error> {
+    let e = resolve(id)?;
+    println!("id:      {}", e.id);
+    println!("name:    {}", e.name);
+    println!("created: {}", e.created);
+    println!("source:  {}", e.source);
+    println!("path:    {}", e.path.display());
+
+    if !e.path.is_dir() {
+        println!("(directory is missing)");
+        return Ok(());
+    }
+    let mut files: Vec<String> = fs::read_dir(&e.path)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    files.sort();
+    println!("files:   {}", files.join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str) -> StoreEntry {
+        StoreEntry {
+            id: id.to_string(),
+            name: "test".to_string(),
+            created: "2026-01-01 00:00:00".to_string(),
+            path: PathBuf::from(format!("/configs/{}", id)),
+            source: "backup".to_string(),
+        }
+    }
+
+    #[test]
+    fn colliding_ids_grow_then_get_a_counter() {
+        let dir = Path::new("/configs/new");
+        let mut index = StoreIndex::default();
+        assert_eq!(unique_id(&index, dir), short_id(dir, 8));
+
+        index.backups.push(entry(&short_id(dir, 8)));
+        assert_eq!(unique_id(&index, dir), short_id(dir, 9));
+
+        // Every length taken, up to the whole hash
+        index.backups = (8..=16).map(|len| entry(&short_id(dir, len))).collect();
+        let full = short_id(dir, 16);
+        assert_eq!(unique_id(&index, dir), format!("{}-2", full));
+        index.backups.push(entry(&format!("{}-2", full)));
+        assert_eq!(unique_id(&index, dir), format!("{}-3", full));
+    }
+
+    #[test]
+    fn find_takes_unique_prefixes_and_rejects_empty_ones() {
+        let index = StoreIndex { backups: vec![entry("abc12345"), entry("abd12345"), entry("abc12345-2")] };
+        assert_eq!(find(&index, "abd").unwrap().id, "abd12345");
+        // An exact match beats the longer ID sharing its prefix
+        assert_eq!(find(&index, "abc12345").unwrap().id, "abc12345");
+        assert_eq!(find(&index, "ab").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(find(&index, "ffff").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(find(&index, "").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let one = StoreIndex { backups: vec![entry("abc12345")] };
+        assert!(find(&one, "").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+// src/cli.rs
+
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::args::formats::Format;
+use crate::args::managers::{self, ManagerDefs};
+use crate::args::verbosity;
+use crate::backup::store;
+use crate::restore::restore_file;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "horns",
+    version,
+    about = "Back up and restore a Rhino Linux installation"
+)]
+pub struct Cli {
+    /// Print parsed configs and internal steps
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    pub verbose: bool,
+
+    /// Only print errors and results
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// User config directory searched for Packages.toml [default: $XDG_CONFIG_HOME/horns]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub config_dir: Option<PathBuf>,
+
+    /// Extra manager definitions, layered over the built-in, system and user files
+    #[arg(long, global = true, value_name = "FILE")]
+    pub managers: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Capture installed packages into a new backup
+    Backup {
+        /// Name for the backup directory
+        name: String,
+    },
+    /// Restore from a local directory, a remote repository or a backup ID
+    Restore(RestoreArgs),
+    /// Validate a config against Packages.toml without running anything
+    Validate(SourceArgs),
+    /// Print the commands a restore would run (same as `restore --dry-run`)
+    Plan(PlanArgs),
+    /// Inspect the package manager definitions
+    #[command(subcommand)]
+    Managers(ManagersCommand),
+    /// Manage stored backups
+    #[command(subcommand)]
+    Backups(BackupsCommand),
+    /// Convert configs from other tools into a horns config
+    #[command(subcommand)]
+    Import(ImportCommand),
+    /// Translate a config between JSON, TOML, YAML and NUON
+    Convert(ConvertArgs),
+    /// Print the JSON Schema for configs, including the merged manager definitions
+    Schema {
+        /// Write the schema here instead of printing it
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Upgrade a config file to the current version in place (the original is kept as .bak, or .bak.N if that exists)
+    Migrate {
+        /// Config file to rewrite
+        file: PathBuf,
+    },
+}
+
+/// Where a config comes from: exactly one of a directory, a git remote or a backup ID.
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("source").required(true).args(["path", "remote", "id"])))]
+pub struct SourceArgs {
+    /// Local directory containing exactly one config
+    #[arg(short, long, value_name = "DIR")]
+    pub path: Option<PathBuf>,
+
+    /// Git repository to clone into the backup store
+    #[arg(short, long, value_name = "URL")]
+    pub remote: Option<String>,
+
+    /// ID (or unique prefix) from `backups list`
+    pub id: Option<String>,
+}
+
+impl SourceArgs {
+    /// Resolve to a directory on disk, cloning a remote if needed.
+    pub fn dir(&self) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        if let Some(path) = &self.path {
+            return Ok(path.clone());
+        }
+        if let Some(link) = &self.remote {
+            return restore_file::fetch(link);
+        }
+        let id = self.id.as_deref().unwrap_or_default();
+        let entry = store::resolve(id)?;
+        if !verbosity::quiet() {
+            println!("Using backup {} from {}", entry.id, entry.path.display());
+        }
+        Ok(entry.path)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Print the ordered commands instead of running them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Emit the dry-run plan as JSON
+    #[arg(long, requires = "dry_run")]
+    pub json: bool,
+
+    /// Continue an interrupted restore, skipping the steps its journal records as done
+    /// (use --path or the backup ID; a --remote source is cloned afresh every time)
+    #[arg(long, conflicts_with = "dry_run")]
+    pub resume: bool,
+
+    /// How many managers may install at once; only managers that don't depend on each
+    /// other and share no lock run together
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
+}
+
+#[derive(Debug, Args)]
+pub struct PlanArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Emit the plan as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ManagersCommand {
+    /// List manager ids and the layer each was defined in
+    List,
+    /// Print the merged definitions, where each entry came from and the search path
+    Show,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupsCommand {
+    /// List stored backups
+    List,
+    /// Show details of one backup
+    Show { id: String },
+    /// Remove a backup from the store
+    Rm { id: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ImportCommand {
+    /// Convert a rhino-deploy deploy.nuon; keys without an equivalent are reported
+    Nuon {
+        /// The deploy.nuon file
+        file: PathBuf,
+
+        /// Write the config here instead of printing it
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// Config to read; the format comes from its extension
+    pub input: PathBuf,
+
+    /// Write here instead of printing; the format comes from its extension unless --to is given
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Output format
+    #[arg(long, value_enum)]
+    pub to: Option<Format>,
+}
+
+impl Cli {
+    /// Resolve the layered manager definitions for this invocation.
+    pub fn manager_defs(&self) -> Result<ManagerDefs, std::io::Error> {
+        managers::resolve(self.config_dir.as_deref(), self.managers.as_deref())
+    }
+}
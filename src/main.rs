@@ -1,47 +1,188 @@
-use std::env;
+use clap::Parser;
+use std::process::ExitCode;
 
 mod args;
+mod backup;
+mod cli;
 mod restore;
-use crate::restore::restore_file::remote;
 
-fn main() {
-    let args: Vec<String> = env::args().skip(1).collect();
+use crate::args::formats::{self, Format};
+use crate::args::{import, json_and_config, managers, migrate, schema, verbosity};
+use crate::cli::{BackupsCommand, Cli, Command, ImportCommand, ManagersCommand};
+use crate::restore::{check, restore_file};
 
-    // ---------------------------------I/O---------------------------------
-    match args.as_slice() {
-        // Backup
-        [cmd, path] if cmd.as_str() == "backup" => {
-            println!("Backup command with path: {}", path);
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    // Manager definitions are loaded only by the commands that use them, so a broken
+    // Packages.toml layer doesn't get in the way of listing backups or converting a file
+    match &cli.command {
+        Command::Backup { name } => {
+            let managers = cli.manager_defs()?;
+            backup::backup_file::local(name, &managers.defs)?;
         }
 
-        // Restore command with path
-        [cmd, opt, path]
-            if cmd.as_str() == "restore" && (opt.as_str() == "--path" || opt.as_str() == "-p") =>
-        {
-            println!("Restore from path: {}", path);
+        Command::Restore(args) => {
+            // Every --remote run clones into a fresh directory, which has no journal yet
+            if args.resume && args.source.remote.is_some() {
+                return Err("--resume can't be used with --remote: each --remote run clones a new copy. \
+                    Resume by the backup ID the first run registered: `horns restore <ID> --resume`"
+                    .into());
+            }
+            let managers = cli.manager_defs()?;
+            let dir = args.source.dir()?;
+            if args.dry_run {
+                restore_file::plan(&dir, &managers, args.json)?;
+            } else {
+                restore_file::local(&dir, &managers, args.resume, args.jobs.into())?;
+            }
         }
 
-        // Restore command with remote
-        [cmd, opt, repo]
-            if cmd.as_str() == "restore"
-                && (opt.as_str() == "--remote" || opt.as_str() == "-r") =>
-        {
-            restore::restore_file::remote(repo);
+        Command::Validate(source) => {
+            let managers = cli.manager_defs()?;
+            let dir = source.dir()?;
+            check::json_validation(&dir, &managers)?;
+            println!("✅ {} is valid", dir.display());
         }
 
-        // Restore command with Backup ID
-        [cmd, id] if cmd.as_str() == "restore" => {
-            println!("Default restore with ID: {}", id);
+        Command::Plan(args) => {
+            let managers = cli.manager_defs()?;
+            let dir = args.source.dir()?;
+            restore_file::plan(&dir, &managers, args.json)?;
         }
 
-        _ => {
-            println!("Invalid command format");
-            println!("Usage examples:");
-            println!("  backup /some/path");
-            println!("  restore --remote myrepo");
-            println!("  restore -p /backup/path");
-            println!("  restore backup123");
+        Command::Managers(ManagersCommand::List) => {
+            let managers = cli.manager_defs()?;
+            let mut ids: Vec<&String> = managers.defs.keys().collect();
+            ids.sort();
+            for id in ids {
+                println!("{:<12} {}", id, managers.origins[id]);
+            }
+            let mut disabled: Vec<(&String, &String)> = managers.disabled.iter().collect();
+            disabled.sort();
+            for (id, origin) in disabled {
+                println!("{:<12} disabled by {}", id, origin);
+            }
+        }
+
+        Command::Managers(ManagersCommand::Show) => {
+            let managers = cli.manager_defs()?;
+            println!("Search path (lowest priority first):");
+            println!("  built-in");
+            for path in managers::search_path(cli.config_dir.as_deref(), cli.managers.as_deref()) {
+                let found = managers.layers.contains(&path.display().to_string());
+                println!("  {}{}", path.display(), if found { "" } else { " (not found)" });
+            }
+            println!("\nMerged definitions:");
+            print!("{}", json_and_config::describe_defs(&managers.defs, &managers.origins));
+            let mut disabled: Vec<(&String, &String)> = managers.disabled.iter().collect();
+            disabled.sort();
+            for (id, origin) in disabled {
+                println!(" - {} (disabled by {})", id, origin);
+            }
+            println!("\nSoftware presets:");
+            print!("{}", json_and_config::describe_software(&managers));
+            let mut disabled: Vec<(&String, &String)> = managers.disabled_software.iter().collect();
+            disabled.sort();
+            for (id, origin) in disabled {
+                println!(" - {} (disabled by {})", id, origin);
+            }
+        }
+
+        Command::Backups(BackupsCommand::List) => backup::store::print_list()?,
+        Command::Backups(BackupsCommand::Show { id }) => backup::store::print_show(id)?,
+        Command::Backups(BackupsCommand::Rm { id }) => {
+            backup::store::remove(id)?;
+        }
+
+        Command::Import(ImportCommand::Nuon { file, output }) => {
+            let managers = cli.manager_defs()?;
+            let (config, unmapped) = import::from_nuon(file, &managers)?;
+            for d in &unmapped {
+                eprintln!("Not imported: {}: {}", file.display(), d);
+            }
+            let json = serde_json::to_string_pretty(&config)?;
+            match output {
+                Some(out) => {
+                    std::fs::write(out, json + "\n")?;
+                    if !verbosity::quiet() {
+                        println!("Wrote {}", out.display());
+                    }
+                }
+                None => println!("{}", json),
+            }
+            if !unmapped.is_empty() {
+                eprintln!("{} key(s) had no equivalent and were left out", unmapped.len());
+            }
+        }
+
+        Command::Convert(args) => {
+            let to = args
+                .to
+                .or_else(|| args.output.as_deref().and_then(Format::from_path))
+                .ok_or("pass --to, or an --output file with a known extension")?;
+            // Going through Config rejects anything horns couldn't restore, with its location
+            let (_, value, _) = formats::read_config(&args.input)
+                .map_err(|d| format!("{}: {}", args.input.display(), d))?;
+            let text = formats::render(to, &value)
+                .map_err(|e| format!("cannot write {:?}: {}", to, e))?;
+            match &args.output {
+                Some(out) => {
+                    std::fs::write(out, text.trim_end().to_string() + "\n")?;
+                    if !verbosity::quiet() {
+                        println!("Wrote {}", out.display());
+                    }
+                }
+                None => println!("{}", text.trim_end()),
+            }
+        }
+
+        Command::Schema { output } => {
+            let managers = cli.manager_defs()?;
+            let text = serde_json::to_string_pretty(&schema::config_schema(&managers))?;
+            match output {
+                Some(out) => {
+                    std::fs::write(out, text + "\n")?;
+                    if !verbosity::quiet() {
+                        println!("Wrote {}", out.display());
+                    }
+                }
+                None => println!("{}", text),
+            }
+        }
+
+        Command::Migrate { file } => match migrate::migrate_file(file)? {
+            Some((from, backup)) => println!(
+                "Migrated {} from version {} to {} (original kept as {})",
+                file.display(),
+                from,
+                migrate::CONFIG_VERSION,
+                backup.display()
+            ),
+            None => println!(
+                "{} is already at version {}",
+                file.display(),
+                migrate::CONFIG_VERSION
+            ),
+        },
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // JSON output must stay machine-readable, so it implies --quiet
+    let json = match &cli.command {
+        Command::Restore(args) => args.json,
+        Command::Plan(args) => args.json,
+        _ => false,
+    };
+    verbosity::set(cli.verbose, cli.quiet || json);
+
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
         }
     }
-    // ---------------------------------I/O---------------------------------
 }
@@ -1,41 +1,69 @@
-use crate::args::json_to_array;
 use crate::args::json_to_array::Config;
-use crate::args::terminal;
+use crate::args::formats::{self, Format};
 use crate::args::json_and_config;
-use std::io;
+use crate::args::managers::ManagerDefs;
+use crate::args::verbosity;
+use std::{ffi::OsString, fs, io, path::Path};
 
-pub fn json_validation(directory_name: &str) -> Result<Config, io::Error> {
-    println!("Scanning: {}", directory_name);
+pub fn json_validation(
+    directory: &Path,
+    defs: &ManagerDefs,
+) -> Result<Config, io::Error> {
+    if !verbosity::quiet() {
+        println!("Scanning: {}", directory.display());
+    }
 
-    // Run `ls`, get space-separated output
-    let output = terminal::output_access(&format!("ls {}", directory_name))?;
-    let files: Vec<_> = output
-        .split_whitespace()
-        .filter(|f| f.ends_with(".json"))
+    // Only look at the top level of the directory; subdirectories (e.g. .git) are ignored
+    let mut files: Vec<OsString> = fs::read_dir(directory)
+        .map_err(|e| io::Error::new(e.kind(), format!("Cannot read directory {}: {}", directory.display(), e)))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name())
+        .filter(|f| Format::from_path(Path::new(f)).is_some())
         .collect();
+    files.sort();
 
     match files.as_slice() {
-        [] => Err(io::Error::new(io::ErrorKind::InvalidInput, "No JSON files found")),
+        [] => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "No config files (.{}) found in {}",
+                formats::EXTENSIONS.join(", ."),
+                directory.display()
+            ),
+        )),
         [single] => {
-            // Build the full path to the single JSON file we found
-            let full_path = format!("{}/{}", directory_name.trim_end_matches('/'), single);
-            println!("✅ Found JSON: {full_path}");
+            // Build the full path to the single config file we found
+            let full_path = directory.join(single);
+            let name = single.to_string_lossy();
+            if !verbosity::quiet() {
+                println!("✅ Found config: {}", full_path.display());
+            }
 
-            // Validate JSON vs TOML and print the report
-            let report = json_and_config::compare_and_report(&full_path, "args/Packages.toml")
-                .map_err(|e| {
-                    eprintln!("Validation error: {}", e);
-                    io::Error::new(io::ErrorKind::Other, e)
+            // Validate the config vs TOML and print the report
+            let (config, report) = json_and_config::compare_and_report(&full_path, defs)
+                .map_err(|diags| {
+                    for d in &diags {
+                        eprintln!("Validation error: {}: {}", name, d);
+                    }
+                    io::Error::other(format!("{} validation error(s) in {}", diags.len(), full_path.display()))
                 })?;
-            println!("{}", report);
+            if !verbosity::quiet() {
+                println!("{}", report);
+            }
 
-            // Parse JSON into Config and return it
-            let config = json_to_array::convert(&full_path)?;
+            if verbosity::verbose() {
+                println!("{:#?}", config); // print the parsed Config
+            }
             Ok(config)
         }
         _ => Err(io::Error::new(
             io::ErrorKind::InvalidInput,
-            "More than one JSON file found",
+            format!(
+                "More than one config file found in {} ({}); keep exactly one",
+                directory.display(),
+                files.iter().map(|f| f.to_string_lossy()).collect::<Vec<_>>().join(", ")
+            ),
         )),
     }
 }
@@ -0,0 +1,212 @@
+// src/restore/journal.rs
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::args::json_to_array::Config;
+use crate::restore::session::StepPhase;
+
+/// First line of a journal: the config the steps belong to.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    config: Value,
+}
+
+/// One line per completed step.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    phase: StepPhase,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    command: String,
+}
+
+/// Completed restore steps, appended to `<config dir>.horns-journal` (JSON lines) as they
+/// finish so `restore --resume` can skip them after an interruption.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    done: HashMap<(StepPhase, Option<String>, String), usize>, // step -> times still to skip
+}
+
+/// The journal sits next to the config directory, not in it, so it's never taken for a config.
+pub fn path_for(dir: &Path) -> Result<PathBuf, io::Error> {
+    let dir = dir.canonicalize()?;
+    let name = dir.file_name().and_then(|n| n.to_str()).unwrap_or("config");
+    Ok(dir.with_file_name(format!("{}.horns-journal", name)))
+}
+
+impl Journal {
+    /// Start a fresh journal for `config`, replacing any old one.
+    pub fn start(dir: &Path, config: &Config) -> Result<Journal, io::Error> {
+        let path = path_for(dir)?;
+        let header = Header { config: serde_json::to_value(config)? };
+        let mut file = File::create(&path)?;
+        writeln!(file, "{}", serde_json::to_string(&header)?)?;
+        file.sync_data()?;
+        Ok(Journal { path, file, done: HashMap::new() })
+    }
+
+    /// Reopen the journal of an interrupted restore of `config`.
+    pub fn resume(dir: &Path, config: &Config) -> Result<Journal, io::Error> {
+        let path = path_for(dir)?;
+        let text = fs::read_to_string(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "No restore journal at {} ({}); run `restore` without --resume",
+                    path.display(),
+                    e
+                ),
+            )
+        })?;
+        let mut lines = text.lines();
+        let header: Header = lines
+            .next()
+            .and_then(|l| serde_json::from_str(l).ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Corrupt restore journal {}", path.display()),
+                )
+            })?;
+        if header.config != serde_json::to_value(config)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The config changed since the interrupted restore ({}); run `restore` without --resume",
+                    path.display()
+                ),
+            ));
+        }
+
+        let mut done: HashMap<(StepPhase, Option<String>, String), usize> = HashMap::new();
+        for line in lines {
+            // A line cut short by the interruption just means that step didn't finish
+            let Ok(entry) = serde_json::from_str::<Entry>(line) else { continue };
+            *done.entry((entry.phase, entry.scope, entry.command)).or_default() += 1;
+        }
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Journal { path, file, done })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Steps the interrupted run completed.
+    pub fn completed(&self) -> usize {
+        self.done.values().sum()
+    }
+
+    /// Whether this step already completed in the interrupted run. Each journal line skips
+    /// one occurrence, so a command that legitimately runs twice still runs the second time.
+    pub fn skip(&mut self, phase: StepPhase, scope: Option<&str>, command: &str) -> bool {
+        let key = (phase, scope.map(str::to_string), command.to_string());
+        match self.done.get_mut(&key) {
+            Some(n) if *n > 0 => {
+                *n -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Append a completed step; flushed right away so it survives a reboot.
+    pub fn record(&mut self, phase: StepPhase, scope: Option<&str>, command: &str) {
+        let entry = Entry {
+            phase,
+            scope: scope.map(str::to_string),
+            command: command.to_string(),
+        };
+        let written = serde_json::to_string(&entry)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.file, "{}", line))
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            eprintln!("(warn) could not write restore journal {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// The restore went through; nothing is left to resume.
+    pub fn finish(self) -> Result<(), io::Error> {
+        fs::remove_file(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A fresh, empty config directory under the system temp dir.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("horns-journal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(pre: &[&str]) -> Config {
+        serde_json::from_value(json!({
+            "version": 1,
+            "package_management": { "pre": pre, "post": [] },
+            "custom_packages": { "pre": [], "post": [] },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn skip_consumes_one_recorded_run_each() {
+        let dir = scratch("skip");
+        let config = config(&["echo hi"]);
+        let mut journal = Journal::start(&dir, &config).unwrap();
+        journal.record(StepPhase::Pre, None, "echo hi");
+        journal.record(StepPhase::Pre, None, "echo hi");
+        journal.record(StepPhase::Install, Some("apt"), "sudo apt-get install -y curl");
+        drop(journal);
+
+        let mut journal = Journal::resume(&dir, &config).unwrap();
+        assert_eq!(journal.completed(), 3);
+        assert!(journal.skip(StepPhase::Pre, None, "echo hi"));
+        assert!(journal.skip(StepPhase::Pre, None, "echo hi"));
+        // Recorded twice, so the third run is new
+        assert!(!journal.skip(StepPhase::Pre, None, "echo hi"));
+        // Phase and scope are part of the key
+        assert!(!journal.skip(StepPhase::Install, Some("snap"), "sudo apt-get install -y curl"));
+        assert!(journal.skip(StepPhase::Install, Some("apt"), "sudo apt-get install -y curl"));
+
+        journal.finish().unwrap();
+        assert!(!path_for(&dir).unwrap().exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resume_refuses_a_changed_config() {
+        let dir = scratch("changed");
+        let mut journal = Journal::start(&dir, &config(&["echo one"])).unwrap();
+        journal.record(StepPhase::Pre, None, "echo one");
+        drop(journal);
+
+        let err = Journal::resume(&dir, &config(&["echo two"])).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("config changed"));
+
+        fs::remove_file(path_for(&dir).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resume_without_a_journal_fails() {
+        let dir = scratch("missing");
+        let err = Journal::resume(&dir, &config(&[])).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,5 @@
 pub mod check;
+pub mod journal;
 pub mod restore_file;
 pub mod session;
+pub mod users;
@@ -1,51 +1,132 @@
 // src/restore/restore_file.rs
 
 use crate::restore::check;
+use crate::restore::journal::Journal;
 use crate::restore::session;
-use crate::args::json_and_config::load_toml_defs;
+use crate::args::managers::ManagerDefs;
+use crate::args::json_to_array::Config;
+use crate::args::{terminal, verbosity};
+use crate::backup::store;
 use chrono::Local;
 use std::{
     fs, io,
     path::{Path, PathBuf},
     process::Command,
+    sync::Mutex,
 };
 
-pub fn forward(dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let path_str = dir.to_str().ok_or_else(|| {
-        Box::new(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "path contains non-UTF-8 characters",
-        )) as Box<dyn std::error::Error>
+/// Validate the config in `dir` against the manager definitions.
+fn load(
+    dir: &Path,
+    defs: &ManagerDefs,
+) -> Result<Config, Box<dyn std::error::Error>> {
+    let config = check::json_validation(dir, defs).map_err(|e| {
+        Box::new(io::Error::other(format!("JSON validation failed: {}", e))) as Box<dyn std::error::Error>
     })?;
 
-    let config = check::json_validation(path_str).map_err(|e| {
-        Box::new(io::Error::new(
-            io::ErrorKind::Other,
-            format!("JSON validation failed: {}", e),
-        )) as Box<dyn std::error::Error>
-    })?;
+    Ok(config)
+}
 
-    // Load TOML definitions (adjust path if Packages.toml lives elsewhere)
-    let toml_path = "Packages.toml";
-    let toml_defs = load_toml_defs(toml_path).map_err(|e| {
-        Box::new(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Failed to load TOML definitions from {}: {}", toml_path, e),
-        )) as Box<dyn std::error::Error>
-    })?;
+/// Run the restore. With `resume`, steps the journal of an interrupted run holds are skipped;
+/// `jobs` is how many independent managers may run at once.
+pub fn forward(
+    dir: &Path,
+    defs: &ManagerDefs,
+    resume: bool,
+    jobs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = load(dir, defs)?;
+
+    let journal = if resume {
+        let journal = Journal::resume(dir, &config)?;
+        if !verbosity::quiet() {
+            println!(
+                "Resuming from {} ({} step(s) already done)",
+                journal.path().display(),
+                journal.completed()
+            );
+        }
+        journal
+    } else {
+        Journal::start(dir, &config)?
+    };
+
+    // Call session with both JSON config and the merged definitions
+    if verbosity::verbose() {
+        println!("DEBUG: about to call start_session");
+    }
+    let journal = Mutex::new(journal);
+    let report = session::start_session(&config, defs, &terminal::Bash, false, Some(&journal), jobs);
+    if verbosity::verbose() {
+        println!("DEBUG: returned from start_session");
+    }
 
-    // Call session with both JSON config and TOML defs
-    println!("DEBUG: about to call start_session");
-    session::start_session(&config, &toml_defs);
-    println!("DEBUG: returned from start_session");
+    session::print_summary(&report);
+    if report.failed() {
+        return Err(Box::new(io::Error::other(format!(
+            "restore finished with failures: {} package(s), {} script(s); fix them and run `restore --resume`",
+            report.failed_packages(),
+            report.failed_scripts()
+        ))));
+    }
+
+    journal.into_inner().unwrap_or_else(|e| e.into_inner()).finish()?;
+    Ok(())
+}
 
+/// Dry run: print the ordered commands a restore of `dir` would run, without running any.
+pub fn plan(
+    dir: &Path,
+    defs: &ManagerDefs,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    ensure_dir(dir)?;
+    let config = load(dir, defs)?;
+    let report = session::start_session(&config, defs, &terminal::Bash, true, None, 1);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report.steps)?);
+    } else {
+        let order: Vec<&str> = report
+            .managers
+            .iter()
+            .filter(|m| m.status != session::ManagerStatus::Disabled)
+            .map(|m| m.id.as_str())
+            .collect();
+        if !order.is_empty() {
+            println!("Manager order: {}", order.join(" → "));
+        }
+        session::print_plan(&report.steps);
+    }
     Ok(())
 }
 
 
+fn ensure_dir(dir: &Path) -> Result<(), io::Error> {
+    if dir.is_dir() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", dir.display()),
+        ))
+    }
+}
+
+/// Restore from a config directory already on disk.
+pub fn local(
+    dir: &Path,
+    defs: &ManagerDefs,
+    resume: bool,
+    jobs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    ensure_dir(dir)?;
+    forward(dir, defs, resume, jobs)
+}
+
+
 pub fn generate_directory(directory_name: &str) -> Result<PathBuf, io::Error> {
     let now = Local::now();
-    let dir = PathBuf::from("configs").join(format!(
+    let dir = store::configs_root()?.join(format!(
         "{}-{}",
         now.format("%Y-%m-%d-%H-%M-%S"),
         directory_name
@@ -54,13 +135,16 @@ pub fn generate_directory(directory_name: &str) -> Result<PathBuf, io::Error> {
     fs::create_dir_all(&dir)?;
 
     let absolute = dir.canonicalize()?;
-    println!("Created directory: {}", absolute.display());
+    if !verbosity::quiet() {
+        println!("Created directory: {}", absolute.display());
+    }
 
     Ok(absolute)
 }
 
 
-pub fn remote(link: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Clone a remote config repository into the backup store and return its directory.
+pub fn fetch(link: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
     let sanitized = link
         .replace("http://", "")
         .replace("https://", "")
@@ -76,13 +160,13 @@ pub fn remote(link: &str) -> Result<(), Box<dyn std::error::Error>> {
         .status()?;
 
     if !status.success() {
-        return Err(Box::new(io::Error::new(
-            io::ErrorKind::Other,
-            format!("git clone failed with exit code: {:?}", status.code()),
-        )));
+        return Err(Box::new(io::Error::other(format!(
+            "git clone failed with exit code: {:?}",
+            status.code()
+        ))));
     }
 
-    forward(dir.as_path())?;
+    store::register(&dir, &sanitized, link)?;
 
-    Ok(())
+    Ok(dir)
 }
@@ -1,220 +1,1123 @@
 // src/restore/session.rs
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, LazyLock, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::args::json_to_array::{Config, ManagerSpec};
-use crate::args::json_and_config::TomlPackageManager;
+use crate::args::json_and_config::{TomlPackageManager, TypedFlag};
+use crate::args::managers::ManagerDefs;
+use crate::args::terminal::Executor;
+use crate::args::verbosity;
+use crate::restore::journal::Journal;
+use crate::restore::users::{self, UserReport, UserStatus};
 
-#[path = "../args/terminal.rs"]
-mod terminal;
+
+/// One command the session ran, or would run in a dry run.
+#[derive(Debug, Serialize)]
+pub struct PlanStep {
+    pub phase: StepPhase,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manager: Option<String>, // manager id; the preset id in the software phase, the user name in the user phase
+    pub command: String,
+    pub status: StepStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<&'static str>, // when a planned step would actually run, e.g. "if check fails"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+/// The part of the session a step belongs to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum StepPhase {
+    Pre,
+    Check,
+    Enable,
+    Bootstrap,
+    Flag,
+    Query,
+    Install,
+    Software,
+    User,
+    Post,
+}
+
+impl fmt::Display for StepPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StepPhase::Pre => "pre",
+            StepPhase::Check => "check",
+            StepPhase::Enable => "enable",
+            StepPhase::Bootstrap => "bootstrap",
+            StepPhase::Flag => "flag",
+            StepPhase::Query => "query",
+            StepPhase::Install => "install",
+            StepPhase::Software => "software",
+            StepPhase::User => "user",
+            StepPhase::Post => "post",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Ok,
+    Failed,
+    Present,  // check exited 0
+    Missing,  // check exited non-zero
+    Resumed,  // done by the interrupted run (restore --resume), not run again
+    Rejected, // package name refused before running anything; the reason is in stderr
+    Planned,  // dry run
+}
+
+impl fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StepStatus::Ok => "ok",
+            StepStatus::Failed => "failed",
+            StepStatus::Present => "present",
+            StepStatus::Missing => "missing",
+            StepStatus::Resumed => "resumed",
+            StepStatus::Rejected => "rejected",
+            StepStatus::Planned => "planned",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ManagerStatus {
+    Ready,       // present after check/enable/bootstrap; installs were attempted
+    Planned,     // dry run
+    Disabled,    // turned off in the JSON config
+    Undefined,   // not in Packages.toml
+    Unavailable, // still missing after enable/bootstrap
+    Crashed,     // its parallel run panicked
+}
+
+impl fmt::Display for ManagerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ManagerStatus::Ready => "ready",
+            ManagerStatus::Planned => "planned",
+            ManagerStatus::Disabled => "disabled",
+            ManagerStatus::Undefined => "undefined",
+            ManagerStatus::Unavailable => "unavailable",
+            ManagerStatus::Crashed => "crashed",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageStatus {
+    Installed,
+    AlreadyInstalled, // found by is_installed / list, install skipped
+    Failed,
+    Rejected, // name didn't match the manager's package_pattern
+    Planned,  // dry run
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageReport {
+    pub name: String,
+    pub status: PackageStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManagerReport {
+    pub id: String,
+    pub status: ManagerStatus,
+    pub packages: Vec<PackageReport>,
+    pub duration_ms: u64,
+}
+
+/// What `start_session` did (or would do): every step in order plus a per-manager summary.
+#[derive(Debug, Serialize)]
+pub struct SessionReport {
+    pub dry_run: bool,
+    pub steps: Vec<PlanStep>,
+    pub managers: Vec<ManagerReport>,
+    pub users: Vec<UserReport>,
+}
+
+impl SessionReport {
+    /// Users that could not be created or updated.
+    pub fn failed_users(&self) -> usize {
+        self.users.iter().filter(|u| u.status == UserStatus::Failed).count()
+    }
+
+    /// Failed pre/post scripts, flag hooks and software post-steps. Enable failures aren't counted: enable routinely fails
+    /// before bootstrap has installed the manager, and an unusable manager shows up as unavailable.
+    pub fn failed_scripts(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|s| {
+                matches!(s.phase, StepPhase::Pre | StepPhase::Post | StepPhase::Flag | StepPhase::Software)
+                    && s.status == StepStatus::Failed
+            })
+            .count()
+    }
+
+    /// Packages that failed to install or were rejected.
+    pub fn failed_packages(&self) -> usize {
+        self.managers
+            .iter()
+            .flat_map(|m| &m.packages)
+            .filter(|p| matches!(p.status, PackageStatus::Failed | PackageStatus::Rejected))
+            .count()
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed_scripts() > 0
+            || self.failed_packages() > 0
+            || self.failed_users() > 0
+            || self
+                .managers
+                .iter()
+                .any(|m| {
+                    matches!(m.status, ManagerStatus::Undefined | ManagerStatus::Unavailable | ManagerStatus::Crashed)
+                })
+    }
+}
+
+/// Runs (or, in a dry run, only records) the session's commands in order.
+pub(super) struct Recorder<'a> {
+    exec: &'a dyn Executor,
+    pub(super) dry_run: bool,
+    steps: Vec<PlanStep>,
+    journal: Option<&'a Mutex<Journal>>, // completed commands, for `restore --resume`
+    log: Option<RefCell<Vec<(bool, String)>>>, // buffered (is_stderr, line) output of a parallel manager
+}
+
+impl Recorder<'_> {
+    /// Chatter for interactive runs; a dry run only reports its plan.
+    pub(super) fn say(&self, msg: &str) {
+        if !self.dry_run {
+            self.print(msg);
+        }
+    }
+
+    /// Print a progress line (nothing with --quiet), or hold it back while this recorder
+    /// runs a manager in parallel. Errors go through `eprint`, which is never silenced.
+    fn print(&self, msg: &str) {
+        if verbosity::quiet() {
+            return;
+        }
+        match &self.log {
+            Some(log) => log.borrow_mut().push((false, msg.to_string())),
+            None => println!("{}", msg),
+        }
+    }
+
+    /// Like `print`, for stderr.
+    fn eprint(&self, msg: &str) {
+        match &self.log {
+            Some(log) => log.borrow_mut().push((true, msg.to_string())),
+            None => eprintln!("{}", msg),
+        }
+    }
+
+    /// A recorder for one manager run on another thread: same executor and journal, its own
+    /// steps, and output held back until `flush_log`.
+    fn worker(&self) -> Self {
+        Recorder {
+            exec: self.exec,
+            dry_run: self.dry_run,
+            steps: Vec::new(),
+            journal: self.journal,
+            log: Some(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Print the held-back output as one block, every line prefixed with `[prefix]`.
+    fn flush_log(&self, prefix: &str) {
+        let Some(log) = &self.log else { return };
+        for (is_err, msg) in log.borrow_mut().drain(..) {
+            for line in msg.lines().filter(|l| !l.trim().is_empty()) {
+                if is_err {
+                    eprintln!("[{}] {}", prefix, line);
+                } else {
+                    println!("[{}] {}", prefix, line);
+                }
+            }
+        }
+    }
+
+    /// Run a manager's check. In a dry run the result is unknown (`None`).
+    fn check(&mut self, mgr_id: &str, check: &str) -> Option<bool> {
+        self.check_as(StepPhase::Check, Some(mgr_id), check)
+    }
+
+    /// Run a check recorded under `phase`. In a dry run the result is unknown (`None`).
+    pub(super) fn check_as(&mut self, phase: StepPhase, scope: Option<&str>, check: &str) -> Option<bool> {
+        if self.dry_run {
+            // An empty check means "present" without running anything, even in a dry run
+            if check.trim().is_empty() {
+                return Some(true);
+            }
+            self.push(phase, scope, check, StepStatus::Planned);
+            return None;
+        }
+        let present = self.run_check(check);
+        if !check.trim().is_empty() {
+            self.push(phase, scope, check, if present { StepStatus::Present } else { StepStatus::Missing });
+        }
+        Some(present)
+    }
+
+    /// Run a query and return its stdout. In a dry run nothing runs and there is no answer (`None`);
+    /// a failed query is recorded and also gives `None`.
+    fn query(&mut self, scope: &str, cmd: &str) -> Option<String> {
+        if self.dry_run {
+            self.push(StepPhase::Query, Some(scope), cmd, StepStatus::Planned);
+            return None;
+        }
+        let result = self.exec.run(cmd.trim());
+        let ok = result.is_ok();
+        self.push(StepPhase::Query, Some(scope), cmd, if ok { StepStatus::Ok } else { StepStatus::Failed });
+        match result {
+            Ok(stdout) => Some(stdout),
+            Err(e) => {
+                if let Some(step) = self.steps.last_mut() {
+                    step.stderr = Some(e.to_string()).filter(|e| !e.trim().is_empty());
+                }
+                None
+            }
+        }
+    }
+
+    /// Run a command. `condition` describes when a dry-run step would actually happen.
+    pub(super) fn run(
+        &mut self,
+        phase: StepPhase,
+        mgr_id: Option<&str>,
+        cmd: &str,
+        condition: Option<&'static str>,
+    ) -> bool {
+        self.run_with(phase, mgr_id, cmd, condition, false)
+    }
+
+    /// Like `run`, but with the terminal attached so the command can prompt.
+    pub(super) fn run_interactive(
+        &mut self,
+        phase: StepPhase,
+        scope: Option<&str>,
+        cmd: &str,
+        condition: Option<&'static str>,
+    ) -> bool {
+        self.run_with(phase, scope, cmd, condition, true)
+    }
+
+    fn run_with(
+        &mut self,
+        phase: StepPhase,
+        mgr_id: Option<&str>,
+        cmd: &str,
+        condition: Option<&'static str>,
+        interactive: bool,
+    ) -> bool {
+        if self.dry_run {
+            self.push(phase, mgr_id, cmd, StepStatus::Planned);
+            if let Some(step) = self.steps.last_mut() {
+                step.condition = condition;
+            }
+            return true;
+        }
+        // Finished before the interruption; don't run it twice
+        if let Some(journal) = self.journal
+            && lock(journal).skip(phase, mgr_id, cmd.trim())
+        {
+            self.print(&format!("(resume) already done: {}", cmd.trim()));
+            self.push(phase, mgr_id, cmd, StepStatus::Resumed);
+            return true;
+        }
+        let started = Instant::now();
+        let result = if interactive {
+            self.print(&format!("$ {}", cmd.trim()));
+            self.exec.run_interactive(cmd.trim()).map_err(|e| e.to_string())
+        } else {
+            self.run_cmd(cmd)
+        };
+        let ok = result.is_ok();
+        self.push(phase, mgr_id, cmd, if ok { StepStatus::Ok } else { StepStatus::Failed });
+        if ok && let Some(journal) = self.journal {
+            lock(journal).record(phase, mgr_id, cmd.trim());
+        }
+        if let Some(step) = self.steps.last_mut() {
+            step.stderr = result.err().filter(|e| !e.trim().is_empty());
+            step.duration_ms = Some(started.elapsed().as_millis() as u64);
+        }
+        ok
+    }
+
+    /// stderr of the most recent failed step recorded under `scope`.
+    pub(super) fn last_error(&self, scope: &str) -> Option<String> {
+        self.steps
+            .iter()
+            .rev()
+            .filter(|s| s.manager.as_deref() == Some(scope) && s.status == StepStatus::Failed)
+            .find_map(|s| s.stderr.clone())
+    }
+
+    /// Run the check command string; empty = treat as present (true).
+    /// Uses the executor and prints captured stdout/stderr for debug.
+    fn run_check(&self, check: &str) -> bool {
+        let c = check.trim();
+        if c.is_empty() {
+            self.print("(check) empty check string — treating as present");
+            return true;
+        }
+
+        self.print(&format!("(check) running: {}", c));
+        match self.exec.run(c) {
+            Ok(stdout) => {
+                if !stdout.trim().is_empty() {
+                    self.print(&format!("(check stdout) {}", stdout.trim_end()));
+                } else {
+                    self.print("(check) command returned success with no stdout");
+                }
+                self.print("(check) considered present (exit 0)");
+                true
+            }
+            Err(stderr) => {
+                // A failing check is an answer, not an error
+                self.print(&format!("(check stderr) {}", stderr));
+                self.print("(check) considered not present (non-zero exit)");
+                false
+            }
+        }
+    }
+
+    /// Run a shell command string through the executor so we can capture and print output.
+    /// Returns the captured stderr on failure.
+    fn run_cmd(&self, cmd: &str) -> Result<(), String> {
+        let c = cmd.trim();
+        if c.is_empty() {
+            self.print("(run_cmd) empty command, skipping");
+            return Ok(());
+        }
+        self.print(&format!("$ {}", c));
+
+        match self.exec.run(c) {
+            Ok(stdout) => {
+                if !stdout.trim().is_empty() {
+                    self.print(&format!("(out) {}", stdout.trim_end()));
+                }
+                self.print("(run_cmd) succeeded");
+                Ok(())
+            }
+            Err(stderr) => {
+                self.eprint(&format!("(err) {}", stderr));
+                self.print("(run_cmd) failed");
+                Err(stderr.to_string())
+            }
+        }
+    }
+
+    pub(super) fn push(&mut self, phase: StepPhase, mgr_id: Option<&str>, cmd: &str, status: StepStatus) {
+        self.steps.push(PlanStep {
+            phase,
+            manager: mgr_id.map(str::to_string),
+            command: cmd.trim().to_string(),
+            status,
+            condition: None,
+            stderr: None,
+            duration_ms: None,
+        });
+    }
+}
 
 /// Start session: fully dynamic, consults TOML for what to run.
 /// Flags are applied (via placeholder expansion) before bootstrap when required.
-pub fn start_session(config: &Config, toml_defs: &HashMap<String, TomlPackageManager>) {
-    println!("=== SESSION START (dynamic) ===");
+/// With `dry_run` nothing is executed (not even checks); the returned steps are the plan.
+/// With a `journal`, every command that succeeds is recorded in it, and commands it already
+/// holds (from an interrupted run) are skipped. Checks and queries always run again.
+/// `jobs` > 1 lets independent managers run at the same time (never in a dry run).
+pub fn start_session(
+    config: &Config,
+    defs: &ManagerDefs,
+    exec: &dyn Executor,
+    dry_run: bool,
+    journal: Option<&Mutex<Journal>>,
+    jobs: usize,
+) -> SessionReport {
+    let mut rec = Recorder { exec, dry_run, steps: Vec::new(), journal, log: None };
+    let mut managers: Vec<ManagerReport> = Vec::new();
+    rec.say("=== SESSION START (dynamic) ===");
 
     // Run pre scripts
     for script in &config.package_management.pre {
         let s = script.trim();
         if s.is_empty() { continue; }
-        info("pre", s);
-        let _ = run_cmd(s);
+        if !dry_run {
+            info("pre", s);
+        }
+        rec.run(StepPhase::Pre, None, s, None);
     }
 
-    // For each manager requested in JSON
-    for (mgr_id, spec) in &config.package_management.managers {
-        // Is it enabled in JSON?
-        if !is_enabled(spec) {
-            println!("(skip) manager '{}' disabled in JSON", mgr_id);
+    // Software presets turned on in JSON; their packages join their manager's list
+    let mut presets: Vec<&String> = config.software.iter().filter(|(_, on)| **on).map(|(id, _)| id).collect();
+    presets.sort();
+    let mut pkgs_by_manager: HashMap<&str, Vec<String>> = HashMap::new();
+    for (mgr, pkgs) in &config.custom_packages.by_manager {
+        pkgs_by_manager.entry(mgr).or_default().extend(pkgs.iter().cloned());
+    }
+    for id in &presets {
+        let Some(preset) = defs.software.get(*id) else {
+            eprintln!("(error) unknown software preset '{}' — not defined in Packages.toml; skipping", id);
             continue;
+        };
+        let list = pkgs_by_manager.entry(&preset.manager).or_default();
+        for pkg in &preset.packages {
+            if !list.contains(pkg) {
+                list.push(pkg.clone());
+            }
         }
+    }
 
-        println!("\nknock knock → '{}'", mgr_id);
+    // For each manager requested in JSON (or implied by a preset or a `requires`), in dependency order
+    let implied = ManagerSpec::Bool(true);
+    let mut mgr_ids: Vec<&str> = config.package_management.managers.keys().map(String::as_str).collect();
+    for id in &presets {
+        if let Some(preset) = defs.software.get(*id)
+            && !mgr_ids.contains(&preset.manager.as_str())
+        {
+            mgr_ids.push(&preset.manager);
+        }
+    }
+    let mut i = 0;
+    while i < mgr_ids.len() {
+        let id = mgr_ids[i];
+        i += 1;
+        let enabled = config.package_management.managers.get(id).is_none_or(is_enabled);
+        let Some(def) = defs.defs.get(id).filter(|_| enabled) else { continue };
+        for dep in &def.requires {
+            match config.package_management.managers.get(dep) {
+                Some(spec) if !is_enabled(spec) => rec.say(&format!(
+                    "(warn) '{}' requires '{}', which the config disables; '{}' may not work",
+                    id, dep, id
+                )),
+                _ if mgr_ids.contains(&dep.as_str()) => {}
+                _ => mgr_ids.push(dep),
+            }
+        }
+    }
+    let runs: Vec<ManagerRun> = defs
+        .run_order(&mgr_ids)
+        .into_iter()
+        .map(|id| {
+            let spec = config.package_management.managers.get(id).unwrap_or(&implied);
+            (id, spec, pkgs_by_manager.get(id).cloned().unwrap_or_default())
+        })
+        .collect();
+    if jobs > 1 && !dry_run {
+        managers = run_parallel(&mut rec, defs, runs, jobs);
+    } else {
+        for (mgr_id, spec, pkgs) in runs {
+            let started = Instant::now();
+            let (status, packages) = run_manager(&mut rec, &defs.defs, mgr_id, spec, pkgs, || {});
+            managers.push(ManagerReport {
+                id: mgr_id.to_string(),
+                status,
+                packages,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+    }
 
-        // Lookup TOML definition
-        let def = match toml_defs.get(mgr_id) {
-            Some(d) => d,
-            None => {
-                eprintln!("(error) unknown manager '{}' — not defined in Packages.toml; skipping", mgr_id);
-                continue;
+    // Preset post-steps, once every package of the preset made it in
+    for id in &presets {
+        let Some(preset) = defs.software.get(*id) else { continue };
+        if preset.post.is_empty() {
+            continue;
+        }
+        let installed = preset.packages.iter().all(|pkg| {
+            managers
+                .iter()
+                .filter(|m| m.id == preset.manager)
+                .flat_map(|m| &m.packages)
+                .any(|p| {
+                    &p.name == pkg
+                        && matches!(
+                            p.status,
+                            PackageStatus::Installed | PackageStatus::AlreadyInstalled | PackageStatus::Planned
+                        )
+                })
+        });
+        if !installed {
+            rec.say(&format!("(skip) software '{}': packages not installed, skipping post steps", id));
+            continue;
+        }
+        for step in &preset.post {
+            let s = step.trim();
+            if s.is_empty() { continue; }
+            if !dry_run {
+                info("software", s);
             }
-        };
+            rec.run(StepPhase::Software, Some(&preset.id), s, None);
+        }
+    }
 
-        // Show the check string from TOML
-        if !def.check.trim().is_empty() {
-            println!("json_and_config: wait, I'll check it for you");
-            println!("→ check command: {}", def.check);
+    // Users after packages (so shells/groups from packages exist), before post scripts
+    let users = users::apply_users(&mut rec, &config.users);
+
+    // Run post scripts
+    for script in &config.package_management.post {
+        let s = script.trim();
+        if s.is_empty() { continue; }
+        if !dry_run {
+            info("post", s);
         }
+        rec.run(StepPhase::Post, None, s, None);
+    }
 
-        // Run initial check (treat empty check as present)
-        let mut present = run_check(&def.check);
-        if present {
-            println!("json_and_config: yup — installed ✅");
-        } else {
-            println!("json_and_config: nope — not installed ❌");
-            // If there's an enable command, run it now (before flags/bootstrap)
-            if !def.enable.trim().is_empty() {
-                println!("-> running enable for '{}': {}", mgr_id, def.enable);
-                let _ = run_cmd(&def.enable);
+    rec.say("=== SESSION COMPLETE ===");
+    SessionReport { dry_run, steps: rec.steps, managers, users }
+}
+
+/// A manager to run: its id, its spec from the config and its packages.
+type ManagerRun<'c> = (&'c str, &'c ManagerSpec, Vec<String>);
+
+/// What a parallel manager run reports back to the scheduler.
+enum Event<'a> {
+    Usable(usize),                              // past bootstrap; its bootstrap_locks are free
+    Done(usize, ManagerReport, Recorder<'a>),   // finished, with its held-back output
+}
+
+/// Run managers on up to `jobs` threads. A manager starts once every manager it depends on
+/// (among `runs`) has finished and no running manager holds one of its locks: `locks` are
+/// held for the whole run, `bootstrap_locks` only until the manager is usable. Each one's
+/// output is held back and printed as one block, prefixed with its id, when it finishes.
+/// Reports come back in `runs` order.
+fn run_parallel(
+    rec: &mut Recorder,
+    defs: &ManagerDefs,
+    runs: Vec<ManagerRun>,
+    jobs: usize,
+) -> Vec<ManagerReport> {
+    let ids: Vec<&str> = runs.iter().map(|(id, ..)| *id).collect();
+    let locks = |id: &str| defs.defs.get(id).map(|d| d.locks.as_slice()).unwrap_or_default();
+    let bootstrap_locks =
+        |id: &str| defs.defs.get(id).map(|d| d.bootstrap_locks.as_slice()).unwrap_or_default();
+    let mut reports: Vec<Option<ManagerReport>> = ids.iter().map(|_| None).collect();
+    let mut pending: Vec<(usize, ManagerRun)> = runs.into_iter().enumerate().collect();
+    let mut running: Vec<(usize, bool)> = Vec::new(); // (run, usable yet)
+    rec.say(&format!("(jobs) running up to {} managers at a time", jobs));
+
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        loop {
+            while running.len() < jobs {
+                let held: Vec<&String> = running
+                    .iter()
+                    .flat_map(|&(r, usable)| {
+                        let setup = if usable { &[][..] } else { bootstrap_locks(ids[r]) };
+                        locks(ids[r]).iter().chain(setup)
+                    })
+                    .collect();
+                let ready = pending.iter().position(|(_, (id, ..))| {
+                    let waiting = defs.deps(id).iter().any(|dep| {
+                        ids.iter().position(|i| i == dep).is_some_and(|j| reports[j].is_none())
+                    });
+                    let locked = locks(id).iter().chain(bootstrap_locks(id)).any(|l| held.contains(&l));
+                    !waiting && !locked
+                });
+                // Dependencies are acyclic, so with nothing running the first pending one is always ready
+                let ready = ready.or((running.is_empty() && !pending.is_empty()).then_some(0));
+                let Some(pos) = ready else { break };
+                let (i, (mgr_id, spec, pkgs)) = pending.remove(pos);
+                running.push((i, false));
+                rec.say(&format!("(jobs) '{}' started", mgr_id));
+                let mut worker = rec.worker();
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    let started = Instant::now();
+                    let usable = || {
+                        let _ = tx.send(Event::Usable(i));
+                    };
+                    // A panic must still end in Done, or the loop below would wait for it forever
+                    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+                        run_manager(&mut worker, &defs.defs, mgr_id, spec, pkgs, usable)
+                    }));
+                    let (status, packages) = run.unwrap_or_else(|_| {
+                        worker.eprint(&format!("(error) '{}' crashed; its remaining steps were skipped", mgr_id));
+                        (ManagerStatus::Crashed, Vec::new())
+                    });
+                    let report = ManagerReport {
+                        id: mgr_id.to_string(),
+                        status,
+                        packages,
+                        duration_ms: started.elapsed().as_millis() as u64,
+                    };
+                    let _ = tx.send(Event::Done(i, report, worker));
+                });
+            }
+            if running.is_empty() {
+                break;
+            }
+            match rx.recv() {
+                Ok(Event::Usable(i)) => {
+                    if let Some(run) = running.iter_mut().find(|(r, _)| *r == i) {
+                        run.1 = true;
+                    }
+                }
+                Ok(Event::Done(i, report, worker)) => {
+                    running.retain(|&(r, _)| r != i);
+                    worker.flush_log(&report.id);
+                    rec.say(&format!("(jobs) '{}' finished in {:.1}s", report.id, report.duration_ms as f64 / 1000.0));
+                    rec.steps.extend(worker.steps);
+                    reports[i] = Some(report);
+                }
+                Err(_) => break,
             }
         }
+    });
+    reports.into_iter().flatten().collect()
+}
 
-        // Gather flags from JSON (owned map so we can pass a reference easily)
-        let flags_map: HashMap<String, Value> = match spec {
-            ManagerSpec::Bool(_) => HashMap::new(),
-            ManagerSpec::Object { flags, .. } => flags.clone(),
-        };
+/// Check, enable, bootstrap and install one manager. `usable` is called once the manager's
+/// setup (check, enable, bootstrap) is over, before any installs.
+fn run_manager(
+    rec: &mut Recorder,
+    toml_defs: &HashMap<String, TomlPackageManager>,
+    mgr_id: &str,
+    spec: &ManagerSpec,
+    pkgs: Vec<String>,
+    usable: impl FnOnce(),
+) -> (ManagerStatus, Vec<PackageReport>) {
+    let mut packages: Vec<PackageReport> = Vec::new();
+    // Is it enabled in JSON?
+    if !is_enabled(spec) {
+        rec.say(&format!("(skip) manager '{}' disabled in JSON", mgr_id));
+        return (ManagerStatus::Disabled, packages);
+    }
 
-        // IMPORTANT: apply flag-driven enable-like actions that SHOULD run before bootstrap.
-        // Expand enable using flags and run if it produced a changed/meaningful command.
-        let flagged_enable = expand_with_flags(&def.enable, &flags_map, None);
-        if should_run_expanded(&flagged_enable, &def.enable) {
-            println!("-> running flagged enable for '{}': {}", mgr_id, flagged_enable);
-            let _ = run_cmd(&flagged_enable);
-        }
-
-        // Re-check after enable + flagged-enable
-        present = run_check(&def.check);
-
-        // If still missing, attempt bootstrap (expanded with flags first)
-        if !present && !def.bootstrap.trim().is_empty() {
-            let flagged_bootstrap = expand_with_flags(&def.bootstrap, &flags_map, None);
-            let to_run = if should_run_expanded(&flagged_bootstrap, &def.bootstrap) {
-                flagged_bootstrap
-            } else {
-                def.bootstrap.clone()
-            };
-            println!("-> attempting bootstrap for '{}': {}", mgr_id, to_run);
-            let _ = run_cmd(&to_run);
-        }
-
-        // Final check
-        present = run_check(&def.check);
-        if !present {
-            eprintln!(
-                "(warn) '{}' still not available after enable/bootstrap — skipping installs",
-                mgr_id
-            );
-            continue;
+    rec.say(&format!("\nknock knock → '{}'", mgr_id));
+
+    // Lookup TOML definition
+    let def = match toml_defs.get(mgr_id) {
+        Some(d) => d,
+        None => {
+            rec.eprint(&format!("(error) unknown manager '{}' — not defined in Packages.toml; skipping", mgr_id));
+            return (ManagerStatus::Undefined, packages);
         }
+    };
 
-        // Install packages one-by-one (expand {{package}} and any {{flag}} placeholders)
-        let pkgs = config
-            .custom_packages
-            .by_manager
-            .get(mgr_id)
-            .cloned()
-            .unwrap_or_default();
+    // Show the check string from TOML
+    if !def.check.trim().is_empty() {
+        rec.say("json_and_config: wait, I'll check it for you");
+        rec.say(&format!("→ check command: {}", def.check));
+    }
 
-        if pkgs.is_empty() {
-            println!("(info) no packages for '{}'", mgr_id);
-            continue;
+    // Run initial check (treat empty check as present)
+    let mut present = rec.check(mgr_id, &def.check);
+    if present == Some(true) {
+        rec.say("json_and_config: yup — installed ✅");
+    } else {
+        rec.say("json_and_config: nope — not installed ❌");
+        // If there's an enable command, run it now (before flags/bootstrap)
+        if !def.enable.trim().is_empty() {
+            rec.say(&format!("-> running enable for '{}': {}", mgr_id, def.enable));
+            rec.run(StepPhase::Enable, Some(mgr_id), &def.enable, Some("if check fails"));
         }
+    }
 
-        println!("(info) installing {} package(s) via '{}'", pkgs.len(), mgr_id);
-        for pkg in pkgs {
-            // Expand flags and package in one pass
-            let cmd = expand_with_flags(&def.install, &flags_map, Some(("package", &pkg)));
-            println!("-> [{}] {}", mgr_id, cmd);
-            let _ = run_cmd(&cmd);
+    // Gather flags from JSON on top of the defaults declared in Packages.toml
+    let mut flags_map: HashMap<String, Value> = def
+        .others
+        .iter()
+        .flatten()
+        .filter_map(|(name, flag)| flag.default().map(|d| (name.clone(), d.clone())))
+        .collect();
+    if let ManagerSpec::Object { flags, .. } = spec {
+        flags_map.extend(flags.clone());
+    }
+
+    // IMPORTANT: apply flag-driven enable-like actions that SHOULD run before bootstrap.
+    // Expand enable using flags and run if it produced a changed/meaningful command.
+    let flagged_enable = expand_with_flags(&def.enable, &flags_map, None);
+    if should_run_expanded(&flagged_enable, &def.enable) {
+        rec.say(&format!("-> running flagged enable for '{}': {}", mgr_id, flagged_enable));
+        rec.run(StepPhase::Enable, Some(mgr_id), &flagged_enable, None);
+    }
+
+    // Re-check after enable + flagged-enable
+    present = rec.check(mgr_id, &def.check);
+
+    // If still missing, attempt bootstrap (expanded with flags first)
+    if present != Some(true) && !def.bootstrap.trim().is_empty() {
+        let flagged_bootstrap = expand_with_flags(&def.bootstrap, &flags_map, None);
+        let to_run = if should_run_expanded(&flagged_bootstrap, &def.bootstrap) {
+            flagged_bootstrap
+        } else {
+            def.bootstrap.clone()
+        };
+        rec.say(&format!("-> attempting bootstrap for '{}': {}", mgr_id, to_run));
+        rec.run(StepPhase::Bootstrap, Some(mgr_id), &to_run, Some("if still missing"));
+    }
+
+    // Final check
+    present = rec.check(mgr_id, &def.check);
+    usable();
+    if present == Some(false) {
+        rec.eprint(&format!(
+            "(warn) '{}' still not available after enable/bootstrap — skipping installs",
+            mgr_id
+        ));
+        return (ManagerStatus::Unavailable, packages);
+    }
+
+    // Flags that are on, in name order, for their before/args/after hooks
+    let mut active: Vec<(&String, &TypedFlag)> = def
+        .others
+        .iter()
+        .flatten()
+        .filter_map(|(name, flag)| Some((name, flag.hooks(flags_map.get(name)?)?)))
+        .collect();
+    active.sort_by_key(|(name, _)| *name);
+
+    for (name, flag) in &active {
+        if let Some(before) = &flag.before {
+            let cmd = expand_with_flags(before, &flags_map, None);
+            rec.say(&format!("-> [{}] {} (flag '{}')", mgr_id, cmd, name));
+            rec.run(StepPhase::Flag, Some(mgr_id), &cmd, None);
         }
     }
 
-    // Run post scripts
-    for script in &config.package_management.post {
-        let s = script.trim();
-        if s.is_empty() { continue; }
-        info("post", s);
-        let _ = run_cmd(s);
+    // Install packages one-by-one (expand {{package}} and any {{flag}} placeholders)
+    let ready = if rec.dry_run { ManagerStatus::Planned } else { ManagerStatus::Ready };
+    if pkgs.is_empty() {
+        rec.say(&format!("(info) no packages for '{}'", mgr_id));
+    } else {
+        install_packages(rec, def, mgr_id, &active, &flags_map, pkgs, &mut packages);
     }
 
-    println!("=== SESSION COMPLETE ===");
+    for (name, flag) in &active {
+        if let Some(after) = &flag.after {
+            let cmd = expand_with_flags(after, &flags_map, None);
+            rec.say(&format!("-> [{}] {} (flag '{}')", mgr_id, cmd, name));
+            rec.run(StepPhase::Flag, Some(mgr_id), &cmd, None);
+        }
+    }
+
+    (ready, packages)
 }
 
-/// Small helper: whether ManagerSpec is enabled
-fn is_enabled(spec: &ManagerSpec) -> bool {
-    match spec {
-        ManagerSpec::Bool(b) => *b,
-        ManagerSpec::Object { enabled, .. } => *enabled,
+/// The install template with the active flags' `args` spliced in: at `{{args}}` if the
+/// template has one, otherwise in front of the first `{{<slot>}}` or `{{!<slot>}}` (slot is
+/// package or packages). Args stay unexpanded so the whole command is still expanded in a
+/// single pass.
+fn install_template(install: &str, active: &[(&String, &TypedFlag)], slot: &str) -> String {
+    let args: Vec<&str> = active.iter().filter_map(|(_, f)| f.args.as_deref()).collect();
+    let args = args.join(" ");
+    if install.contains("{{args}}") {
+        install.replace("{{args}}", &args)
+    } else if args.is_empty() {
+        install.to_string()
+    } else {
+        let quoted = format!("{{{{{}}}}}", slot);
+        let raw = format!("{{{{!{}}}}}", slot);
+        match [quoted, raw].iter().filter_map(|p| install.find(p.as_str())).min() {
+            Some(at) => format!("{}{} {}", &install[..at], args, &install[at..]),
+            None => install.to_string(),
+        }
     }
 }
 
-/// Run the check command string; empty = treat as present (true).
-/// Uses terminal::output_access and prints captured stdout/stderr for debug.
-fn run_check(check: &str) -> bool {
-    let c = check.trim();
-    if c.is_empty() {
-        println!("(check) empty check string — treating as present");
-        return true;
+/// Install `pkgs`, recording a report for each: through `install_batch` in chunks of
+/// `batch_size` when the manager has one, otherwise (or to retry a failed batch) one by one.
+fn install_packages(
+    rec: &mut Recorder,
+    def: &TomlPackageManager,
+    mgr_id: &str,
+    active: &[(&String, &TypedFlag)],
+    flags_map: &HashMap<String, Value>,
+    pkgs: Vec<String>,
+    packages: &mut Vec<PackageReport>,
+) {
+    rec.say(&format!("(info) installing {} package(s) via '{}'", pkgs.len(), mgr_id));
+
+    // Names come from the (possibly cloned) config; refuse anything the manager wouldn't accept
+    let mut valid = Vec::new();
+    for pkg in pkgs {
+        match def.check_package(&pkg) {
+            Ok(()) => valid.push(pkg),
+            Err(e) => {
+                rec.eprint(&format!("(error) {}", e));
+                rec.push(StepPhase::Install, Some(mgr_id), &pkg, StepStatus::Rejected);
+                if let Some(step) = rec.steps.last_mut() {
+                    step.stderr = Some(e.clone());
+                }
+                packages.push(PackageReport {
+                    name: pkg,
+                    status: PackageStatus::Rejected,
+                    stderr: Some(e),
+                    duration_ms: 0,
+                });
+            }
+        }
     }
 
-    println!("(check) running: {}", c);
-    match terminal::output_access(c) {
-        Ok(stdout) => {
-            if !stdout.trim().is_empty() {
-                println!("(check stdout) {}", stdout.trim_end());
-            } else {
-                println!("(check) command returned success with no stdout");
+    // Skip what's already there
+    let present = already_installed(rec, def, mgr_id, flags_map, &valid);
+    if !present.is_empty() {
+        rec.say(&format!("(skip) {} package(s) already installed via '{}'", present.len(), mgr_id));
+    }
+    let (done, valid): (Vec<String>, Vec<String>) = valid.into_iter().partition(|p| present.contains(p));
+    for pkg in done {
+        packages.push(PackageReport {
+            name: pkg,
+            status: PackageStatus::AlreadyInstalled,
+            stderr: None,
+            duration_ms: 0,
+        });
+    }
+
+    let single = install_template(&def.install, active, "package");
+    let Some(batch) = &def.install_batch else {
+        for pkg in valid {
+            install_one(rec, mgr_id, &single, flags_map, pkg, packages);
+        }
+        return;
+    };
+    let batch = install_template(batch, active, "packages");
+    for chunk in valid.chunks(def.batch_size()) {
+        if chunk.len() == 1 {
+            install_one(rec, mgr_id, &single, flags_map, chunk[0].clone(), packages);
+            continue;
+        }
+        let cmd = expand_with_flags(&batch, flags_map, Some(("packages", chunk)));
+        rec.say(&format!("-> [{}] {}", mgr_id, cmd));
+        let ok = rec.run(StepPhase::Install, Some(mgr_id), &cmd, None);
+        if !ok {
+            // One bad name fails the whole batch; retry one by one to find it
+            rec.say(&format!(
+                "(warn) batch of {} failed for '{}' — retrying one by one",
+                chunk.len(),
+                mgr_id
+            ));
+            for pkg in chunk {
+                install_one(rec, mgr_id, &single, flags_map, pkg.clone(), packages);
             }
-            println!("(check) considered present (exit 0)");
-            true
+            continue;
         }
-        Err(stderr) => {
-            eprintln!("(check stderr) {}", stderr.to_string());
-            println!("(check) considered not present (non-zero exit)");
-            false
+        // Split the batch's time evenly so the summary still adds up
+        let share = rec.steps.last().and_then(|s| s.duration_ms).unwrap_or(0) / chunk.len() as u64;
+        for pkg in chunk {
+            packages.push(PackageReport {
+                name: pkg.clone(),
+                status: if rec.dry_run { PackageStatus::Planned } else { PackageStatus::Installed },
+                stderr: None,
+                duration_ms: share,
+            });
         }
     }
 }
 
-/// Run a shell command string using terminal::output_access so we can capture and print output.
-/// Returns true on success, false on failure.
-fn run_cmd(cmd: &str) -> bool {
-    let c = cmd.trim();
-    if c.is_empty() {
-        println!("(run_cmd) empty command, skipping");
-        return true;
+/// Which of `pkgs` the manager already has: asked per package through `is_installed`, or
+/// read from one `list` query when there is no is_installed. A dry run can't know, so it
+/// only records the queries it would run.
+fn already_installed(
+    rec: &mut Recorder,
+    def: &TomlPackageManager,
+    mgr_id: &str,
+    flags_map: &HashMap<String, Value>,
+    pkgs: &[String],
+) -> HashSet<String> {
+    let mut present = HashSet::new();
+    if pkgs.is_empty() {
+        return present;
     }
-    println!("$ {}", c);
-
-    match terminal::output_access(c) {
-        Ok(stdout) => {
-            if !stdout.trim().is_empty() {
-                println!("(out) {}", stdout.trim_end());
+    if let Some(template) = &def.is_installed {
+        for pkg in pkgs {
+            let cmd = expand_with_flags(template, flags_map, Some(("package", std::slice::from_ref(pkg))));
+            if rec.check_as(StepPhase::Query, Some(mgr_id), &cmd) == Some(true) {
+                present.insert(pkg.clone());
             }
-            println!("(run_cmd) succeeded");
-            true
         }
-        Err(stderr) => {
-            eprintln!("(err) {}", stderr.to_string());
-            println!("(run_cmd) failed");
-            false
+    } else if let Some(list) = &def.list {
+        let Some(stdout) = rec.query(mgr_id, &list.command) else {
+            return present;
+        };
+        match list.parse(&stdout) {
+            Ok(listed) => present.extend(listed.into_iter().filter(|p| pkgs.contains(p))),
+            Err(e) => rec.eprint(&format!("(warn) list for '{}' failed: {}", mgr_id, e)),
         }
     }
+    present
 }
 
-/// Replace placeholders {{key}} from flags map. If `extra` is Some(("package","name")) it will also replace {{package}}.
-fn expand_with_flags(template: &str, flags: &HashMap<String, Value>, extra: Option<(&str, &str)>) -> String {
-    let mut out = template.to_string();
-    // Replace flags (stringify values)
-    for (k, v) in flags {
-        let placeholder = format!("{{{{{}}}}}", k);
-        let replacement = match v {
-            Value::String(s) => s.clone(),
-            Value::Bool(b) => b.to_string(),
-            Value::Number(n) => n.to_string(),
-            Value::Null => "".to_string(),
-            Value::Array(_) | Value::Object(_) => v.to_string(),
+/// Run the one-package install template for `pkg` and record its report.
+fn install_one(
+    rec: &mut Recorder,
+    mgr_id: &str,
+    template: &str,
+    flags_map: &HashMap<String, Value>,
+    pkg: String,
+    packages: &mut Vec<PackageReport>,
+) {
+    // Expand flags and package in one pass
+    let cmd = expand_with_flags(template, flags_map, Some(("package", std::slice::from_ref(&pkg))));
+    rec.say(&format!("-> [{}] {}", mgr_id, cmd));
+    let ok = rec.run(StepPhase::Install, Some(mgr_id), &cmd, None);
+    let step = rec.steps.last();
+    let status = match (rec.dry_run, ok) {
+        (true, _) => PackageStatus::Planned,
+        (false, true) => PackageStatus::Installed,
+        (false, false) => PackageStatus::Failed,
+    };
+    packages.push(PackageReport {
+        name: pkg,
+        status,
+        stderr: step.and_then(|s| s.stderr.clone()),
+        duration_ms: step.and_then(|s| s.duration_ms).unwrap_or(0),
+    });
+}
+
+/// Print a dry-run plan as a numbered list.
+pub fn print_plan(steps: &[PlanStep]) {
+    println!("=== PLAN (dry run, nothing executed) ===");
+    if steps.is_empty() {
+        println!("(nothing to do)");
+        return;
+    }
+    for (i, step) in steps.iter().enumerate() {
+        let scope = match &step.manager {
+            Some(m) => format!("[{}] {}", m, step.phase),
+            None => format!("[{}]", step.phase),
+        };
+        let status = match (step.status, step.condition) {
+            (StepStatus::Planned, Some(condition)) => format!("would run {}", condition),
+            (StepStatus::Planned, None) => "would run".to_string(),
+            (StepStatus::Rejected, _) => format!("rejected: {}", step.stderr.as_deref().unwrap_or("")),
+            (status, _) => status.to_string(),
         };
-        out = out.replace(&placeholder, &replacement);
+        println!("{:>3}. {:<20} {}", i + 1, scope, step.command);
+        println!("     {:<20} ({})", "", status);
     }
-    // Extra placeholder (e.g., package)
-    if let Some((k, val)) = extra {
-        let placeholder = format!("{{{{{}}}}}", k);
-        out = out.replace(&placeholder, val);
+}
+
+/// Print the per-manager table after a real run, with the stderr of every failed package.
+pub fn print_summary(report: &SessionReport) {
+    println!("\n=== SUMMARY ===");
+    println!("{:<12} {:<12} {:>6} {:>7} {:>9}", "MANAGER", "STATUS", "OK", "FAILED", "TIME");
+    for m in &report.managers {
+        let count = |status: PackageStatus| m.packages.iter().filter(|p| p.status == status).count();
+        let ok = count(PackageStatus::Installed);
+        let already = count(PackageStatus::AlreadyInstalled);
+        let failed = m.packages.len() - ok - already;
+        println!(
+            "{:<12} {:<12} {:>6} {:>7} {:>8.1}s",
+            m.id,
+            m.status.to_string(),
+            ok,
+            failed,
+            m.duration_ms as f64 / 1000.0
+        );
+        if already > 0 {
+            println!("  ✓ {} already installed", already);
+        }
+        let failures = m
+            .packages
+            .iter()
+            .filter(|p| !matches!(p.status, PackageStatus::Installed | PackageStatus::AlreadyInstalled));
+        for p in failures {
+            let reason = p.stderr.as_deref().unwrap_or("").lines().last().unwrap_or("").trim();
+            println!("  ✗ {} ({:.1}s) {}", p.name, p.duration_ms as f64 / 1000.0, reason);
+        }
+    }
+
+    if !report.users.is_empty() {
+        println!("\n{:<12} {:<12}", "USER", "STATUS");
+        for u in &report.users {
+            println!("{:<12} {:<12}", u.name, u.status.to_string());
+            if let Some(err) = u.stderr.as_deref().and_then(|e| e.lines().last()) {
+                println!("  ✗ {}", err.trim());
+            }
+        }
+    }
+
+    let scripts = report.failed_scripts();
+    if scripts > 0 {
+        println!("pre/post/flag/software scripts: {} failed", scripts);
+    }
+    if report.failed() {
+        println!("Result: FAILED");
+    } else {
+        println!("Result: OK");
+    }
+}
+
+/// Small helper: whether ManagerSpec is enabled
+fn is_enabled(spec: &ManagerSpec) -> bool {
+    match spec {
+        ManagerSpec::Bool(b) => *b,
+        ManagerSpec::Object { enabled, .. } => *enabled,
+    }
+}
+
+/// `{{name}}` or `{{!name}}` in a command template.
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{(!?)([A-Za-z0-9_-]+)\}\}").expect("placeholder regex"));
+
+/// Replace placeholders {{key}} from flags map. If `extra` is Some(("package", names)) it will also replace {{package}},
+/// with every name shell-quoted on its own and space-separated (that's how {{packages}} gets a whole batch).
+/// Values are shell-quoted; `{{!key}}` inserts the value raw, for trusted template parts only.
+/// Expansion is a single pass, so a value that itself contains `{{...}}` is never expanded again.
+fn expand_with_flags(template: &str, flags: &HashMap<String, Value>, extra: Option<(&str, &[String])>) -> String {
+    PLACEHOLDER.replace_all(template, |caps: &Captures| {
+        let raw = &caps[1] == "!";
+        let key = &caps[2];
+        let value = match extra {
+            Some((k, vals)) if k == key => {
+                let vals: Vec<String> = vals
+                    .iter()
+                    .map(|v| if raw { v.clone() } else { shell_quote(v) })
+                    .collect();
+                return vals.join(" ");
+            }
+            // Stringify flag values
+            _ => match flags.get(key) {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Bool(b)) => b.to_string(),
+                Some(Value::Number(n)) => n.to_string(),
+                Some(Value::Null) => "".to_string(),
+                Some(v @ (Value::Array(_) | Value::Object(_))) => v.to_string(),
+                // Unknown placeholders are left untouched
+                None => return caps[0].to_string(),
+            },
+        };
+        if raw { value } else { shell_quote(&value) }
+    })
+    .into_owned()
+}
+
+/// Quote a value for `bash -c`. Plain words are left as they are so commands stay readable.
+pub(super) fn shell_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:=@+,%".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
     }
-    out
 }
 
 /// Decide whether an expanded command should be run: non-empty and different from original.
@@ -224,7 +1127,442 @@ fn should_run_expanded(expanded: &str, original: &str) -> bool {
     !e.is_empty() && e != o
 }
 
+/// The journal, even if a manager thread panicked while holding it.
+fn lock(journal: &Mutex<Journal>) -> MutexGuard<'_, Journal> {
+    journal.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 /// Simple info-style print helper
 fn info(stage: &str, s: &str) {
-    println!("> {}: {}", stage, s);
+    if !verbosity::quiet() {
+        println!("> {}: {}", stage, s);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::json_and_config::FlagSpec;
+    use crate::args::managers;
+    use crate::args::terminal::Recording;
+    use serde_json::json;
+    use std::time::Duration;
+
+    const DEFS: &str = r#"
+[[package_manager]]
+id = "fake"
+check = "command -v fake"
+bootstrap = "install-fake"
+enable = "enable-fake"
+install = "fake add {{package}}"
+"#;
+
+    fn config(value: Value) -> Config {
+        serde_json::from_value(value).expect("test config")
+    }
+
+    fn basic() -> Config {
+        config(json!({
+            "version": 1,
+            "package_management": { "pre": ["echo pre"], "post": ["echo post"], "fake": true },
+            "custom_packages": { "pre": [], "post": [], "fake": ["one", "two"] },
+        }))
+    }
+
+    #[test]
+    fn dry_run_runs_nothing_and_plans_every_step() {
+        let defs = managers::from_layers(&[DEFS]).unwrap();
+        let exec = Recording::new();
+        let report = start_session(&basic(), &defs, &exec, true, None, 1);
+
+        assert!(exec.calls().is_empty());
+        let commands: Vec<&str> = report.steps.iter().map(|s| s.command.as_str()).collect();
+        assert_eq!(
+            commands,
+            [
+                "echo pre",
+                "command -v fake",
+                "enable-fake",
+                "command -v fake",
+                "install-fake",
+                "command -v fake",
+                "fake add one",
+                "fake add two",
+                "echo post",
+            ]
+        );
+    }
+
+    #[test]
+    fn real_run_checks_then_installs_each_package() {
+        let defs = managers::from_layers(&[DEFS]).unwrap();
+        let exec = Recording::new();
+        let report = start_session(&basic(), &defs, &exec, false, None, 1);
+
+        // The manager is present, so neither enable nor bootstrap runs
+        assert_eq!(
+            exec.calls(),
+            [
+                "echo pre",
+                "command -v fake",
+                "command -v fake",
+                "command -v fake",
+                "fake add one",
+                "fake add two",
+                "echo post",
+            ]
+        );
+        assert!(!report.failed());
+    }
+
+    #[test]
+    fn missing_manager_is_enabled_and_bootstrapped() {
+        let defs = managers::from_layers(&[DEFS]).unwrap();
+        let exec = Recording::new().fail("command -v fake", "not found");
+        let report = start_session(&basic(), &defs, &exec, false, None, 1);
+
+        assert_eq!(
+            exec.calls(),
+            [
+                "echo pre",
+                "command -v fake",
+                "enable-fake",
+                "command -v fake",
+                "install-fake",
+                "command -v fake",
+                "echo post",
+            ]
+        );
+        assert_eq!(report.managers[0].status, ManagerStatus::Unavailable);
+        assert!(report.failed());
+    }
+
+    #[test]
+    fn failed_install_is_reported_per_package() {
+        let defs = managers::from_layers(&[DEFS]).unwrap();
+        let exec = Recording::new().fail("fake add two", "no such package");
+        let report = start_session(&basic(), &defs, &exec, false, None, 1);
+
+        let statuses: Vec<(&str, PackageStatus)> = report.managers[0]
+            .packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [("one", PackageStatus::Installed), ("two", PackageStatus::Failed)]
+        );
+        assert_eq!(report.failed_packages(), 1);
+    }
+
+    #[test]
+    fn listed_packages_are_not_installed_again() {
+        let list = "[[package_manager]]\nid = \"fake\"\nlist = { command = \"fake list\" }\n";
+        let defs = managers::from_layers(&[DEFS, list]).unwrap();
+        let exec = Recording::new().output("fake list", "one\nthree\n");
+        let report = start_session(&basic(), &defs, &exec, false, None, 1);
+
+        let calls = exec.calls();
+        assert!(calls.contains(&"fake list".to_string()));
+        assert!(!calls.contains(&"fake add one".to_string()));
+        assert!(calls.contains(&"fake add two".to_string()));
+        assert_eq!(report.managers[0].packages[0].status, PackageStatus::AlreadyInstalled);
+    }
+
+    #[test]
+    fn failed_batch_is_retried_one_by_one() {
+        let batch = "[[package_manager]]\nid = \"fake\"\ninstall_batch = \"fake add-all {{packages}}\"\n";
+        let defs = managers::from_layers(&[DEFS, batch]).unwrap();
+        let exec = Recording::new()
+            .fail("fake add-all one two", "no such package: two")
+            .fail("fake add two", "no such package");
+        let report = start_session(&basic(), &defs, &exec, false, None, 1);
+
+        let calls = exec.calls();
+        let installs: Vec<&str> = calls.iter().map(String::as_str).filter(|c| c.starts_with("fake add")).collect();
+        assert_eq!(installs, ["fake add-all one two", "fake add one", "fake add two"]);
+        let statuses: Vec<(&str, PackageStatus)> = report.managers[0]
+            .packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [("one", PackageStatus::Installed), ("two", PackageStatus::Failed)]
+        );
+        assert_eq!(report.failed_packages(), 1);
+    }
+
+    /// The `flatpak install` commands a restore of `pkgs` through the built-in flatpak runs.
+    fn flatpak_installs(pkgs: &[&str], exec: &Recording) -> Vec<String> {
+        let defs = managers::from_layers(&[managers::EMBEDDED]).unwrap();
+        let config = config(json!({
+            "version": 1,
+            "package_management": { "pre": [], "post": [], "flatpak": true },
+            "custom_packages": { "pre": [], "post": [], "flatpak": pkgs },
+        }));
+        start_session(&config, &defs, exec, false, None, 1);
+        exec.calls().into_iter().filter(|c| c.starts_with("flatpak install")).collect()
+    }
+
+    #[test]
+    fn flatpak_installs_never_prompt() {
+        // A lone package goes through the one-by-one template
+        let exec = Recording::new();
+        assert_eq!(flatpak_installs(&["org.a.One"], &exec), ["flatpak install -y org.a.One"]);
+
+        // So does every retry after a failed batch
+        let exec = Recording::new().fail("flatpak install -y org.a.One org.a.Two", "error");
+        assert_eq!(
+            flatpak_installs(&["org.a.One", "org.a.Two"], &exec),
+            [
+                "flatpak install -y org.a.One org.a.Two",
+                "flatpak install -y org.a.One",
+                "flatpak install -y org.a.Two",
+            ]
+        );
+    }
+
+    fn flags(pairs: &[(&str, Value)]) -> HashMap<String, Value> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn expanded_values_are_shell_quoted() {
+        let pkgs = ["evil; rm -rf ~".to_string(), "it's".to_string(), "$(id)".to_string(), "plain-1.0".to_string()];
+        let cmd = expand_with_flags("install {{package}}", &HashMap::new(), Some(("package", &pkgs)));
+        assert_eq!(cmd, r#"install 'evil; rm -rf ~' 'it'\''s' '$(id)' plain-1.0"#);
+
+        let flags = flags(&[("remote", json!("a b")), ("on", json!(true)), ("n", json!(3))]);
+        let cmd = expand_with_flags("add {{remote}} {{on}} {{n}}", &flags, None);
+        assert_eq!(cmd, "add 'a b' true 3");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn raw_placeholders_are_not_quoted() {
+        let flags = flags(&[("opts", json!("--user -y"))]);
+        let pkgs = ["a b".to_string()];
+        let cmd = expand_with_flags("install {{!opts}} {{!package}}", &flags, Some(("package", &pkgs)));
+        assert_eq!(cmd, "install --user -y a b");
+    }
+
+    #[test]
+    fn expansion_is_a_single_pass() {
+        // A value that looks like a placeholder stays literal, and unknown placeholders are left alone
+        let flags = flags(&[("a", json!("{{b}}")), ("b", json!("oops"))]);
+        assert_eq!(expand_with_flags("run {{!a}} {{c}}", &flags, None), "run {{b}} {{c}}");
+        assert_eq!(expand_with_flags("run {{a}}", &flags, None), "run '{{b}}'");
+    }
+
+    #[test]
+    fn flag_args_go_in_front_of_either_package_placeholder() {
+        let defs = managers::from_layers(&[managers::EMBEDDED]).unwrap();
+        let pacstall = &defs.defs["pacstall"];
+        let noconfirm = pacstall.others.as_ref().unwrap().get("noconfirm").unwrap();
+        let FlagSpec::Typed(flag) = noconfirm else { panic!("noconfirm is typed") };
+        let name = "noconfirm".to_string();
+        let active = [(&name, flag)];
+        assert_eq!(install_template("pacstall -I {{package}}", &active, "package"), "pacstall -I -P {{package}}");
+        assert_eq!(install_template("pacstall -I {{!package}}", &active, "package"), "pacstall -I -P {{!package}}");
+        assert_eq!(install_template("x {{args}} -- {{!packages}}", &active, "packages"), "x -P -- {{!packages}}");
+        assert_eq!(install_template("pacstall -I {{package}}", &[], "package"), "pacstall -I {{package}}");
+    }
+
+    #[test]
+    fn flag_hooks_run_around_the_installs() {
+        let defs = managers::from_layers(&[managers::EMBEDDED]).unwrap();
+        let config = config(json!({
+            "version": 1,
+            "package_management": {
+                "pre": [], "post": [],
+                "apt": { "enabled": true, "update": true },
+                "pacstall": { "enabled": true, "noconfirm": true },
+                "flatpak": { "enabled": true, "install_flatseal": true },
+            },
+            "custom_packages": {
+                "pre": [], "post": [],
+                "apt": ["curl"], "pacstall": ["nala-deb"], "flatpak": ["org.a.One"],
+            },
+        }));
+        let exec = Recording::new()
+            .fail("dpkg-query -W -f='${Status}' curl 2>/dev/null | grep -q 'ok installed'", "");
+        let report = start_session(&config, &defs, &exec, false, None, 1);
+        assert!(!report.failed());
+
+        let calls = exec.calls();
+        let at = |cmd: &str| calls.iter().position(|c| c == cmd).unwrap_or_else(|| panic!("{} never ran: {:?}", cmd, calls));
+        // apt's `update` runs before its installs
+        assert!(at("sudo apt-get update") < at("sudo apt-get install -y curl"));
+        // pacstall's `noconfirm` adds -P
+        at("pacstall -I -P nala-deb");
+        // flatpak's `install_flatseal` runs after its installs
+        assert!(at("flatpak install -y org.a.One") < at("flatpak install -y flathub com.github.tchx84.Flatseal"));
+    }
+
+    fn apport(apt: Value) -> Config {
+        config(json!({
+            "version": 1,
+            "package_management": { "pre": [], "post": ["echo post"] },
+            "custom_packages": { "pre": [], "post": [], "apt": apt },
+            "software": { "apport": true },
+        }))
+    }
+
+    const APPORT_MISSING: &str = "dpkg-query -W -f='${Status}' apport 2>/dev/null | grep -q 'ok installed'";
+    const APPORT_POST: &str = "sudo systemctl enable --now apport.service";
+
+    #[test]
+    fn software_presets_install_through_their_manager_then_run_post() {
+        let defs = managers::from_layers(&[managers::EMBEDDED]).unwrap();
+        let exec = Recording::new().fail(APPORT_MISSING, "");
+        let report = start_session(&apport(json!(["curl"])), &defs, &exec, false, None, 1);
+        assert!(!report.failed());
+
+        // apt isn't in package_management, yet the preset enables it and joins its list
+        let apt = report.managers.iter().find(|m| m.id == "apt").expect("apt ran");
+        assert_eq!(apt.status, ManagerStatus::Ready);
+        let names: Vec<&str> = apt.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["curl", "apport"]);
+
+        let calls = exec.calls();
+        let at = |cmd: &str| calls.iter().position(|c| c == cmd).unwrap_or_else(|| panic!("{} never ran: {:?}", cmd, calls));
+        assert!(at("sudo apt-get install -y apport") < at(APPORT_POST));
+        assert!(at(APPORT_POST) < at("echo post"));
+    }
+
+    #[test]
+    fn software_post_is_skipped_when_its_packages_fail() {
+        let defs = managers::from_layers(&[managers::EMBEDDED]).unwrap();
+        let exec = Recording::new()
+            .fail(APPORT_MISSING, "")
+            .fail("sudo apt-get install -y apport", "E: Unable to locate package apport");
+        let report = start_session(&apport(json!([])), &defs, &exec, false, None, 1);
+
+        assert_eq!(report.failed_packages(), 1);
+        assert!(!exec.calls().iter().any(|c| c == APPORT_POST), "{:?}", exec.calls());
+    }
+
+    const PARALLEL: &str = r#"
+[[package_manager]]
+id = "base"
+check = "command -v base"
+bootstrap = ""
+enable = ""
+install = "base add {{package}}"
+locks = ["pkg"]
+
+[[package_manager]]
+id = "other"
+check = "command -v other"
+bootstrap = ""
+enable = ""
+install = "other add {{package}}"
+locks = ["pkg"]
+
+[[package_manager]]
+id = "app"
+check = "command -v app"
+bootstrap = ""
+enable = ""
+install = "app add {{package}}"
+requires = ["base"]
+
+[[package_manager]]
+id = "alpha"
+check = "command -v alpha"
+bootstrap = "install-alpha"
+enable = ""
+install = "alpha add {{package}}"
+bootstrap_locks = ["pkg"]
+"#;
+
+    fn parallel(managers: &[&str]) -> Config {
+        let mut pm = json!({ "pre": [], "post": [] });
+        let mut cp = json!({ "pre": [], "post": [] });
+        for id in managers {
+            pm[*id] = json!(true);
+            cp[*id] = json!(["p1", "p2", "p3", "p4"]);
+        }
+        config(json!({ "version": 1, "package_management": pm, "custom_packages": cp }))
+    }
+
+    /// Positions of the first and last call that mentions `id`.
+    fn span(calls: &[String], id: &str) -> (usize, usize) {
+        let first = calls.iter().position(|c| c.contains(id)).unwrap();
+        let last = calls.iter().rposition(|c| c.contains(id)).unwrap();
+        (first, last)
+    }
+
+    #[test]
+    fn parallel_runs_wait_for_deps_and_locks() {
+        let defs = managers::from_layers(&[PARALLEL]).unwrap();
+        // Threads interleave differently every time, so give the scheduler a few chances to slip
+        for _ in 0..5 {
+            let exec = Recording::new().delay(Duration::from_millis(2));
+            let report = start_session(&parallel(&["app", "base", "other"]), &defs, &exec, false, None, 3);
+            let calls = exec.calls();
+            assert_eq!(calls.len(), 3 * 7);
+
+            let (base, other, app) = (span(&calls, "base"), span(&calls, "other"), span(&calls, "app"));
+            assert!(base.1 < app.0, "app ran before base finished: {:?}", calls);
+            assert!(base.1 < other.0 || other.1 < base.0, "base and other overlapped: {:?}", calls);
+            let ids: Vec<&str> = report.managers.iter().map(|m| m.id.as_str()).collect();
+            assert_eq!(ids, ["base", "app", "other"]);
+        }
+    }
+
+    #[test]
+    fn bootstrap_locks_are_held_only_through_setup() {
+        let defs = managers::from_layers(&[PARALLEL]).unwrap();
+        for _ in 0..5 {
+            let exec = Recording::new().delay(Duration::from_millis(2)).fail("command -v alpha", "not found");
+            start_session(&parallel(&["alpha", "base"]), &defs, &exec, false, None, 2);
+            let calls = exec.calls();
+
+            // alpha runs first and never becomes usable, so all of it is setup: check, check, bootstrap, check
+            let (alpha, base) = (span(&calls, "alpha"), span(&calls, "base"));
+            assert_eq!(alpha.1 - alpha.0, 3, "alpha's bootstrap overlapped base: {:?}", calls);
+            assert!(alpha.1 < base.0, "base started during alpha's bootstrap: {:?}", calls);
+        }
+    }
+
+    #[test]
+    fn managers_with_bootstrap_locks_still_install() {
+        let defs = managers::from_layers(&[PARALLEL]).unwrap();
+        let exec = Recording::new();
+        let report = start_session(&parallel(&["alpha", "base"]), &defs, &exec, false, None, 2);
+
+        // Both ran to the end; the lock was free again once alpha was usable
+        assert_eq!(exec.calls().len(), 2 * 7);
+        assert!(report.managers.iter().all(|m| m.status == ManagerStatus::Ready));
+    }
+
+    #[test]
+    fn a_crashed_parallel_run_is_reported_not_waited_for() {
+        let defs = managers::from_layers(&[PARALLEL]).unwrap();
+        let exec = Recording::new().panic_on("other add p1");
+        let report = start_session(&parallel(&["app", "base", "other"]), &defs, &exec, false, None, 3);
+
+        let statuses: Vec<(&str, ManagerStatus)> = report.managers.iter().map(|m| (m.id.as_str(), m.status)).collect();
+        assert_eq!(
+            statuses,
+            [("base", ManagerStatus::Ready), ("app", ManagerStatus::Ready), ("other", ManagerStatus::Crashed)]
+        );
+        assert!(report.failed());
+    }
+
+    #[test]
+    fn required_managers_run_first_without_being_listed() {
+        let defs = managers::from_layers(&[PARALLEL]).unwrap();
+        // Only app is in the config; it requires base
+        let exec = Recording::new();
+        let report = start_session(&parallel(&["app"]), &defs, &exec, false, None, 1);
+
+        let ids: Vec<&str> = report.managers.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["base", "app"]);
+        assert_eq!(report.managers[0].status, ManagerStatus::Ready);
+        assert!(report.managers[0].packages.is_empty());
+        assert_eq!(exec.calls().first().map(String::as_str), Some("command -v base"));
+    }
 }
@@ -0,0 +1,259 @@
+// src/restore/users.rs
+
+use serde::Serialize;
+use std::fmt;
+
+use crate::args::json_to_array::{HomeSpec, UserSpec};
+use crate::restore::session::{Recorder, StepPhase, shell_quote as q};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserStatus {
+    Created,
+    Updated,
+    Unchanged,
+    Failed,
+    Planned, // dry run
+}
+
+impl fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UserStatus::Created => "created",
+            UserStatus::Updated => "updated",
+            UserStatus::Unchanged => "unchanged",
+            UserStatus::Failed => "failed",
+            UserStatus::Planned => "planned",
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserReport {
+    pub name: String,
+    pub status: UserStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+}
+
+/// Supplementary groups for a user: `groups` plus `sudo` for root users, without duplicates.
+pub fn wanted_groups(user: &UserSpec) -> Vec<String> {
+    let mut groups = user.groups.clone();
+    if user.root {
+        groups.push("sudo".to_string());
+    }
+    let mut seen = Vec::new();
+    groups.retain(|g| {
+        let new = !seen.contains(g);
+        seen.push(g.clone());
+        new
+    });
+    groups
+}
+
+/// Create or update every user. Each step checks first, so re-running changes nothing.
+pub(super) fn apply_users(rec: &mut Recorder, users: &[UserSpec]) -> Vec<UserReport> {
+    users.iter().map(|user| apply_user(rec, user)).collect()
+}
+
+fn apply_user(rec: &mut Recorder, user: &UserSpec) -> UserReport {
+    rec.say(&format!("\nuser → '{}'", user.name));
+    let name = q(&user.name);
+    let groups = wanted_groups(user);
+    let mut changed = false;
+    let mut ok = true;
+
+    // Groups first: useradd -G fails on a group that doesn't exist
+    for group in &groups {
+        let exists = rec.check_as(
+            StepPhase::User,
+            Some(&user.name),
+            &format!("getent group {}", q(group)),
+        );
+        if exists != Some(true) {
+            ok &= rec.run(
+                StepPhase::User,
+                Some(&user.name),
+                &format!("sudo groupadd {}", q(group)),
+                Some("if group is missing"),
+            );
+            changed = true;
+        }
+    }
+
+    let exists = rec.check_as(StepPhase::User, Some(&user.name), &format!("id -u {}", name));
+
+    if exists != Some(true) {
+        let mut cmd = vec!["sudo useradd".to_string()];
+        match &user.home {
+            HomeSpec::Create(true) => cmd.push("--create-home".to_string()),
+            HomeSpec::Create(false) => cmd.push("--no-create-home".to_string()),
+            HomeSpec::Path(path) => cmd.push(format!("--create-home --home-dir {}", q(path))),
+        }
+        if let Some(comment) = &user.comment {
+            cmd.push(format!("--comment {}", q(comment)));
+        }
+        if !groups.is_empty() {
+            cmd.push(format!("-G {}", q(&groups.join(","))));
+        }
+        cmd.push(name.clone());
+        ok &= rec.run(
+            StepPhase::User,
+            Some(&user.name),
+            &cmd.join(" "),
+            Some("if user is missing"),
+        );
+        changed = true;
+    }
+
+    if exists != Some(false) {
+        // Existing account: add missing groups and fix the comment; the home directory is left alone
+        for group in &groups {
+            let member = rec.check_as(
+                StepPhase::User,
+                Some(&user.name),
+                &format!("id -nG {} | tr ' ' '\\n' | grep -qxF {}", name, q(group)),
+            );
+            if member != Some(true) {
+                let cmd = format!("sudo usermod -a -G {} {}", q(group), name);
+                ok &= rec.run(
+                    StepPhase::User,
+                    Some(&user.name),
+                    &cmd,
+                    Some("if user exists without the group"),
+                );
+                changed = true;
+            }
+        }
+        if let Some(comment) = &user.comment {
+            let same = rec.check_as(
+                StepPhase::User,
+                Some(&user.name),
+                &format!(
+                    "getent passwd {} | cut -d: -f5 | grep -qxF {}",
+                    name,
+                    q(comment)
+                ),
+            );
+            if same != Some(true) {
+                let cmd = format!("sudo usermod --comment {} {}", q(comment), name);
+                ok &= rec.run(
+                    StepPhase::User,
+                    Some(&user.name),
+                    &cmd,
+                    Some("if the comment differs"),
+                );
+                changed = true;
+            }
+        }
+    }
+
+    if user.password && ok {
+        // passwd -S prints "<name> P ..." once a usable password is set
+        let has_password = rec.check_as(
+            StepPhase::User,
+            Some(&user.name),
+            &format!("sudo passwd -S {} | awk '{{print $2}}' | grep -qx P", name),
+        );
+        if has_password != Some(true) {
+            ok &= rec.run_interactive(
+                StepPhase::User,
+                Some(&user.name),
+                &format!("sudo passwd {}", name),
+                Some("if no password is set"),
+            );
+            changed = true;
+        }
+    }
+
+    let status = match (rec.dry_run, ok, changed, exists) {
+        (true, ..) => UserStatus::Planned,
+        (false, false, ..) => UserStatus::Failed,
+        (false, true, false, _) => UserStatus::Unchanged,
+        (false, true, true, Some(true)) => UserStatus::Updated,
+        (false, true, true, _) => UserStatus::Created,
+    };
+    UserReport {
+        name: user.name.clone(),
+        status,
+        stderr: rec.last_error(&user.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::json_to_array::Config;
+    use crate::args::managers;
+    use crate::args::terminal::Recording;
+    use crate::restore::session::start_session;
+    use serde_json::json;
+
+    /// Restore nothing but `users` and return the report's users.
+    fn restore(users: serde_json::Value, exec: &Recording) -> Vec<UserReport> {
+        let config: Config = serde_json::from_value(json!({
+            "version": 1,
+            "package_management": { "pre": [], "post": [] },
+            "custom_packages": { "pre": [], "post": [] },
+            "users": users,
+        }))
+        .expect("test config");
+        let defs = managers::from_layers(&[]).unwrap();
+        start_session(&config, &defs, exec, false, None, 1).users
+    }
+
+    fn bob() -> serde_json::Value {
+        json!([{ "name": "bob", "root": true, "groups": ["docker"], "comment": "Bob O'Neil" }])
+    }
+
+    #[test]
+    fn a_user_with_everything_in_place_is_only_checked() {
+        let exec = Recording::new();
+        let users = restore(bob(), &exec);
+
+        assert_eq!(
+            exec.calls(),
+            [
+                "getent group docker",
+                "getent group sudo",
+                "id -u bob",
+                "id -nG bob | tr ' ' '\\n' | grep -qxF docker",
+                "id -nG bob | tr ' ' '\\n' | grep -qxF sudo",
+                "getent passwd bob | cut -d: -f5 | grep -qxF 'Bob O'\\''Neil'",
+            ]
+        );
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].status, UserStatus::Unchanged);
+    }
+
+    #[test]
+    fn a_missing_user_gets_its_groups_then_the_account() {
+        let exec = Recording::new()
+            .fail("getent group docker", "")
+            .fail("id -u bob", "no such user");
+        let users = restore(bob(), &exec);
+
+        assert_eq!(
+            exec.calls(),
+            [
+                "getent group docker",
+                "sudo groupadd docker",
+                "getent group sudo",
+                "id -u bob",
+                "sudo useradd --create-home --comment 'Bob O'\\''Neil' -G docker,sudo bob",
+            ]
+        );
+        assert_eq!(users[0].status, UserStatus::Created);
+    }
+
+    #[test]
+    fn an_existing_user_is_added_to_missing_groups() {
+        let exec = Recording::new().fail("id -nG bob | tr ' ' '\\n' | grep -qxF docker", "");
+        let users = restore(json!([{ "name": "bob", "groups": ["docker", "video"] }]), &exec);
+
+        let calls = exec.calls();
+        assert!(calls.contains(&"sudo usermod -a -G docker bob".to_string()), "{:?}", calls);
+        assert!(!calls.iter().any(|c| c.contains("useradd") || c.contains("-G video")), "{:?}", calls);
+        assert_eq!(users[0].status, UserStatus::Updated);
+    }
+}
@@ -0,0 +1,85 @@
+// tests/plan_json.rs
+// `plan --json` has to print nothing but the JSON array, whichever way the source is given.
+
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const MANAGERS: &str = r#"
+[[package_manager]]
+id = "fake"
+check = "command -v fake"
+bootstrap = ""
+enable = ""
+install = "fake add {{package}}"
+"#;
+
+const CONFIG: &str = r#"{
+  "version": 1,
+  "package_management": { "pre": ["echo pre"], "post": [], "fake": true },
+  "custom_packages": { "pre": [], "post": [], "fake": ["one"] }
+}"#;
+
+/// A scratch home with a registered backup `abcd1234` and a --managers file.
+fn scratch(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("horns-plan-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let config = root.join("configs/test");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(config.join("horns.json"), CONFIG).unwrap();
+    fs::write(root.join("managers.toml"), MANAGERS).unwrap();
+    let index = serde_json::json!({ "backups": [{
+        "id": "abcd1234",
+        "name": "test",
+        "created": "2026-01-01 00:00:00",
+        "path": config,
+        "source": "backup",
+    }]});
+    fs::create_dir_all(root.join("data/horns")).unwrap();
+    fs::write(root.join("data/horns/index.json"), index.to_string()).unwrap();
+    root
+}
+
+fn plan_json(root: &Path, args: &[&str]) -> Value {
+    let out = Command::new(env!("CARGO_BIN_EXE_rhino-horns"))
+        .env("XDG_DATA_HOME", root.join("data"))
+        .arg("--config-dir")
+        .arg(root.join("no-user-config"))
+        .arg("--managers")
+        .arg(root.join("managers.toml"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    serde_json::from_slice(&out.stdout)
+        .unwrap_or_else(|e| panic!("stdout is not JSON ({}):\n{}", e, String::from_utf8_lossy(&out.stdout)))
+}
+
+#[test]
+fn plan_by_id_prints_only_json() {
+    let root = scratch("id");
+    for args in [&["plan", "abcd", "--json"][..], &["restore", "abcd", "--dry-run", "--json"]] {
+        let steps = plan_json(&root, args);
+        let commands: Vec<&str> = steps
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["command"].as_str().unwrap())
+            .collect();
+        assert_eq!(commands.first(), Some(&"echo pre"));
+        assert!(commands.contains(&"fake add one"), "{:?}", commands);
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn plan_by_path_prints_only_json() {
+    let root = scratch("path");
+    let path = root.join("configs/test");
+    let steps = plan_json(&root, &["plan", "--path", path.to_str().unwrap(), "--json"]);
+    assert_eq!(steps[0]["phase"], "pre");
+    fs::remove_dir_all(&root).unwrap();
+}
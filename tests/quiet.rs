@@ -0,0 +1,52 @@
+// tests/quiet.rs
+// `--quiet` restores print errors, the summary and the result line, and nothing else.
+
+use std::{fs, process::Command};
+
+const MANAGERS: &str = r#"
+[[package_manager]]
+id = "harmless"
+check = "true"
+bootstrap = ""
+enable = ""
+install = "echo installing {{package}}"
+"#;
+
+const CONFIG: &str = r#"{
+  "version": 1,
+  "package_management": { "pre": ["echo pre-step"], "post": ["echo post-step"], "harmless": true },
+  "custom_packages": { "pre": [], "post": [], "harmless": ["one", "two"] }
+}"#;
+
+#[test]
+fn quiet_restore_prints_only_the_summary() {
+    let root = std::env::temp_dir().join(format!("horns-quiet-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let config = root.join("config");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(config.join("horns.json"), CONFIG).unwrap();
+    fs::write(root.join("managers.toml"), MANAGERS).unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rhino-horns"))
+        .arg("--quiet")
+        .arg("--config-dir")
+        .arg(root.join("no-user-config"))
+        .arg("--managers")
+        .arg(root.join("managers.toml"))
+        .arg("restore")
+        .arg("--path")
+        .arg(&config)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{}{}", stdout, stderr);
+    assert_eq!(stderr, "");
+
+    let lines: Vec<&str> = stdout.lines().filter(|l| !l.trim().is_empty()).collect();
+    assert_eq!(lines.first(), Some(&"=== SUMMARY ==="), "{}", stdout);
+    assert_eq!(lines.last(), Some(&"Result: OK"), "{}", stdout);
+    assert_eq!(lines.len(), 4, "{}", stdout); // title, header, the manager's row, result
+    assert!(lines[2].starts_with("harmless"), "{}", stdout);
+    fs::remove_dir_all(&root).unwrap();
+}